@@ -1,17 +1,12 @@
 // examples/streaming_usage.rs
 
 use anthropic_sdk::Client;
-use dotenv::dotenv;
 use serde_json::json;
 use std::sync::{Arc, Mutex};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    dotenv().ok();
-    let secret_key = std::env::var("ANTHROPIC_API_KEY").unwrap_or_default();
-
-    let request = Client::new()
-        .auth(secret_key.as_str())
+    let request = Client::from_env()?
         .model("claude-3-opus-20240229")
         .messages(&json!([
             {"role": "user", "content": "Write me a poem about bravery"}
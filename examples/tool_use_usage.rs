@@ -1,18 +1,12 @@
 // examples/tool_use_usage.rs
 
 use anthropic_sdk::Client;
-use dotenv::dotenv;
 use serde_json::json;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    dotenv().ok();
-    let secret_key = std::env::var("ANTHROPIC_API_KEY").unwrap_or_default();
-
-    let request = Client::new()
-        .auth(secret_key.as_str())
+    let request = Client::from_env()?
         .model("claude-3-opus-20240229")
-        .beta("tools-2024-04-04") // add the beta header
         .tools(&json!([
           {
             "name": "get_weather",
@@ -0,0 +1,102 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::Value;
+
+/// A beta feature, sent in the `anthropic-beta` header. Betas without a variant
+/// yet can be passed as `Other`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Beta {
+    /// Referencing uploaded files with `{"source": {"type": "file", ...}}`.
+    FilesApi,
+    /// The `context_management` request field.
+    ContextManagement,
+    /// Added by `Client::token_efficient_tools`.
+    TokenEfficientTools,
+    /// `cache_control` with `"ttl": "1h"`.
+    ExtendedCacheTtl,
+    InterleavedThinking,
+    Context1m,
+    Other(String),
+}
+
+impl Beta {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::FilesApi => "files-api-2025-04-14",
+            Self::ContextManagement => "context-management-2025-06-27",
+            Self::TokenEfficientTools => "token-efficient-tools-2025-02-19",
+            Self::ExtendedCacheTtl => "extended-cache-ttl-2025-04-11",
+            Self::InterleavedThinking => "interleaved-thinking-2025-05-14",
+            Self::Context1m => "context-1m-2025-08-07",
+            Self::Other(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for Beta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Beta {
+    type Err = Infallible;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Ok(name.into())
+    }
+}
+
+impl From<&str> for Beta {
+    fn from(name: &str) -> Self {
+        let known = [
+            Self::FilesApi,
+            Self::ContextManagement,
+            Self::TokenEfficientTools,
+            Self::ExtendedCacheTtl,
+            Self::InterleavedThinking,
+            Self::Context1m,
+        ];
+        known
+            .into_iter()
+            .find(|beta| beta.as_str() == name)
+            .unwrap_or_else(|| Self::Other(name.to_owned()))
+    }
+}
+
+impl From<String> for Beta {
+    fn from(name: String) -> Self {
+        name.as_str().into()
+    }
+}
+
+/// The betas the features used in `body` depend on.
+pub(crate) fn required(body: &Value) -> Vec<Beta> {
+    let mut betas = Vec::new();
+    if !body["context_management"].is_null() {
+        betas.push(Beta::ContextManagement);
+    }
+    visit(body, &mut |object| {
+        if object["source"]["type"] == "file" {
+            betas.push(Beta::FilesApi);
+        }
+        if object["cache_control"]["ttl"] == "1h" {
+            betas.push(Beta::ExtendedCacheTtl);
+        }
+    });
+    betas
+}
+
+/// Calls `f` with every object nested in `value`.
+fn visit(value: &Value, f: &mut impl FnMut(&Value)) {
+    match value {
+        Value::Object(object) => {
+            f(value);
+            object.values().for_each(|value| visit(value, f));
+        }
+        Value::Array(values) => values.iter().for_each(|value| visit(value, f)),
+        _ => {}
+    }
+}
@@ -0,0 +1,49 @@
+//! Serves a mock Anthropic API until interrupted.
+//!
+//! ```text
+//! anthropic-mock-server [--port 8080] [--script replies.json]
+//! ```
+//!
+//! The script is a JSON array of replies, used in order for message requests.
+//! More can be appended while the server runs with `POST /mock/replies`.
+
+use std::net::SocketAddr;
+
+use anthropic_sdk::{MockServer, ScriptedReply};
+use anyhow::{bail, Context, Result};
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut port = 8080;
+    let mut script = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = args
+                    .next()
+                    .context("--port needs a value")?
+                    .parse()
+                    .context("Invalid --port")?
+            }
+            "--script" => script = Some(args.next().context("--script needs a path")?),
+            _ => bail!("Unknown argument {arg}. Usage: anthropic-mock-server [--port PORT] [--script FILE]"),
+        }
+    }
+
+    let server = MockServer::bind(SocketAddr::from(([127, 0, 0, 1], port))).await?;
+    if let Some(script) = script {
+        let replies: Vec<ScriptedReply> = serde_json::from_str(
+            &std::fs::read_to_string(&script)
+                .with_context(|| format!("Failed to read {script}"))?,
+        )
+        .with_context(|| format!("Invalid script {script}"))?;
+        for reply in replies {
+            server.reply(reply);
+        }
+    }
+
+    println!("Mock Anthropic API listening on {}", server.url());
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
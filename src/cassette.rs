@@ -0,0 +1,399 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::transport::{build_response, SharedTransport};
+use crate::{ReqwestTransport, Transport};
+
+const REDACTED_HEADERS: [&str; 3] = ["x-api-key", "authorization", "proxy-authorization"];
+
+/// A [`Transport`] that records HTTP interactions to a file, or serves them back
+/// from one. Requests match a recorded interaction by method, path and a hash of
+/// their normalized JSON body; each recorded interaction is served once, in order.
+#[derive(Clone)]
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+    state: Arc<Mutex<State>>,
+}
+
+/// `Record(None)` sends through the client's own transport, see `with_inner`.
+#[derive(Clone)]
+enum Mode {
+    Record(Option<SharedTransport>),
+    Replay { timing: bool },
+}
+
+#[derive(Default)]
+struct State {
+    interactions: Vec<Interaction>,
+    used: Vec<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body_hash: String,
+    body: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    chunks: Vec<RecordedChunk>,
+}
+
+/// A piece of a response body and how long after the previous one it arrived.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedChunk {
+    after_ms: u64,
+    data: String,
+}
+
+impl fmt::Debug for Cassette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self.mode {
+            Mode::Record(_) => "record",
+            Mode::Replay { .. } => "replay",
+        };
+        f.debug_struct("Cassette")
+            .field("path", &self.path)
+            .field("mode", &mode)
+            .field(
+                "interactions",
+                &self.state.lock().unwrap().interactions.len(),
+            )
+            .finish()
+    }
+}
+
+impl Cassette {
+    /// Sends requests to the API with the client's HTTP client, timeouts
+    /// included, and writes every interaction to `path`, replacing what was
+    /// there. API keys are redacted.
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: Mode::Record(None),
+            state: Default::default(),
+        }
+    }
+
+    /// Serves requests from the interactions recorded in `path`, with the
+    /// recorded timing between the chunks of each body.
+    pub fn replay(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let file = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read cassette {}", path.display()))?;
+        let file: CassetteFile = serde_json::from_str(&file)
+            .with_context(|| format!("Invalid cassette {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            mode: Mode::Replay { timing: true },
+            state: Arc::new(Mutex::new(State {
+                used: vec![false; file.interactions.len()],
+                interactions: file.interactions,
+            })),
+        })
+    }
+
+    /// Replays `path` if it exists and records it otherwise.
+    pub fn auto(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        if path.exists() {
+            Self::replay(path)
+        } else {
+            Ok(Self::record(path))
+        }
+    }
+
+    /// Records the requests sent through `transport` instead of the client's
+    /// HTTP client. Has no effect when replaying.
+    pub fn through<T: Transport + 'static>(mut self, transport: T) -> Self {
+        if let Mode::Record(_) = self.mode {
+            self.mode = Mode::Record(Some(SharedTransport(Arc::new(transport))));
+        }
+        self
+    }
+
+    /// Set to false to replay bodies as fast as they are read. Defaults to true.
+    pub fn timing(mut self, timing: bool) -> Self {
+        if let Mode::Replay { .. } = self.mode {
+            self.mode = Mode::Replay { timing };
+        }
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn save(&self) -> Result<()> {
+        let file = CassetteFile {
+            interactions: self.state.lock().unwrap().interactions.clone(),
+        };
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(&file)?)
+            .with_context(|| format!("Failed to write cassette {}", self.path.display()))
+    }
+
+    async fn record_send(
+        &self,
+        transport: &SharedTransport,
+        request: reqwest::Request,
+        recorded: RecordedRequest,
+    ) -> Result<reqwest::Response> {
+        let response = transport.0.send(request).await?;
+        let status = response.status().as_u16();
+        let headers = header_pairs(response.headers(), false);
+
+        let recording = Recording {
+            interaction: Interaction {
+                request: recorded,
+                response: RecordedResponse {
+                    status,
+                    headers: headers.clone(),
+                    chunks: Vec::new(),
+                },
+            },
+            cassette: self.clone(),
+            response,
+            last: Instant::now(),
+            pending: Vec::new(),
+        };
+        let body = stream::unfold(Some(recording), |recording| async move {
+            let mut recording = recording?;
+            match recording.response.chunk().await {
+                Ok(Some(chunk)) => {
+                    recording.chunk(&chunk);
+                    Some((Ok(chunk.to_vec()), Some(recording)))
+                }
+                Ok(None) => recording
+                    .finish()
+                    .err()
+                    .map(|error| (Err(std::io::Error::other(error.to_string())), None)),
+                Err(error) => Some((Err(std::io::Error::other(error)), None)),
+            }
+        });
+        build_response(status, &headers, body)
+    }
+
+    fn replay_send(&self, recorded: &RecordedRequest, timing: bool) -> Result<reqwest::Response> {
+        let interaction = {
+            let mut state = self.state.lock().unwrap();
+            let State { interactions, used } = &mut *state;
+            let index = interactions
+                .iter()
+                .zip(used.iter())
+                .position(|(interaction, used)| !used && interaction.request.matches(recorded))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No interaction in cassette {} matches {} {} (body {})",
+                        self.path.display(),
+                        recorded.method,
+                        recorded.path,
+                        recorded.body_hash
+                    )
+                })?;
+            used[index] = true;
+            interactions[index].response.clone()
+        };
+
+        let body = stream::iter(interaction.chunks).then(move |chunk| async move {
+            if timing {
+                tokio::time::sleep(Duration::from_millis(chunk.after_ms)).await;
+            }
+            Ok(chunk.data.into_bytes())
+        });
+        build_response(interaction.status, &interaction.headers, body)
+    }
+}
+
+#[async_trait]
+impl Transport for Cassette {
+    async fn send(&self, request: reqwest::Request) -> Result<reqwest::Response> {
+        let recorded = RecordedRequest::new(&request)?;
+        match &self.mode {
+            Mode::Record(Some(transport)) => self.record_send(transport, request, recorded).await,
+            Mode::Record(None) => {
+                let transport = SharedTransport(Arc::new(ReqwestTransport::default()));
+                self.record_send(&transport, request, recorded).await
+            }
+            Mode::Replay { timing } => self.replay_send(&recorded, *timing),
+        }
+    }
+
+    fn with_inner(&self, inner: ReqwestTransport) -> Option<Arc<dyn Transport>> {
+        match self.mode {
+            Mode::Record(None) => Some(Arc::new(Self {
+                mode: Mode::Record(Some(SharedTransport(Arc::new(inner)))),
+                ..self.clone()
+            })),
+            _ => None,
+        }
+    }
+}
+
+impl RecordedRequest {
+    fn new(request: &reqwest::Request) -> Result<Self> {
+        let bytes = request
+            .body()
+            .and_then(reqwest::Body::as_bytes)
+            .unwrap_or_default();
+        let body = match serde_json::from_slice::<Value>(bytes) {
+            Ok(body) => canonical(&body),
+            Err(_) if bytes.is_empty() => Value::Null,
+            Err(_) => Value::String(String::from_utf8_lossy(bytes).into_owned()),
+        };
+        let url = request.url();
+
+        Ok(Self {
+            method: request.method().to_string(),
+            path: match url.query() {
+                Some(query) => format!("{}?{query}", url.path()),
+                None => url.path().to_owned(),
+            },
+            headers: header_pairs(request.headers(), true),
+            body_hash: body_hash(&body)?,
+            body,
+        })
+    }
+
+    fn matches(&self, other: &RecordedRequest) -> bool {
+        self.method == other.method && self.path == other.path && self.body_hash == other.body_hash
+    }
+}
+
+/// `value` with the keys of every object sorted. serde_json only sorts them
+/// itself while no crate in the build enables its `preserve_order` feature.
+fn canonical(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let sorted: BTreeMap<_, _> = object
+                .iter()
+                .map(|(key, value)| (key.clone(), canonical(value)))
+                .collect();
+            Value::Object(sorted.into_iter().collect())
+        }
+        Value::Array(values) => Value::Array(values.iter().map(canonical).collect()),
+        value => value.clone(),
+    }
+}
+
+/// The SHA-256 of `body` serialized without whitespace, in hex.
+fn body_hash(body: &Value) -> Result<String> {
+    Ok(format!("{:x}", Sha256::digest(serde_json::to_vec(body)?)))
+}
+
+/// A response being passed on to the client while its body is recorded.
+struct Recording {
+    interaction: Interaction,
+    cassette: Cassette,
+    response: reqwest::Response,
+    last: Instant,
+    /// The start of a UTF-8 character split across chunks.
+    pending: Vec<u8>,
+}
+
+impl Recording {
+    fn chunk(&mut self, chunk: &[u8]) {
+        let now = Instant::now();
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(chunk);
+        if let Err(error) = std::str::from_utf8(&bytes) {
+            if error.error_len().is_none() {
+                self.pending = bytes.split_off(error.valid_up_to());
+            }
+        }
+
+        self.interaction.response.chunks.push(RecordedChunk {
+            after_ms: (now - self.last).as_millis() as u64,
+            data: String::from_utf8_lossy(&bytes).into_owned(),
+        });
+        self.last = now;
+    }
+
+    fn finish(mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            self.interaction.response.chunks.push(RecordedChunk {
+                after_ms: 0,
+                data: String::from_utf8_lossy(&pending).into_owned(),
+            });
+        }
+        self.cassette
+            .state
+            .lock()
+            .unwrap()
+            .interactions
+            .push(self.interaction);
+        self.cassette.save()
+    }
+}
+
+fn header_pairs(headers: &HeaderMap, redact: bool) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if redact && REDACTED_HEADERS.contains(&name.as_str()) {
+                "[REDACTED]".to_owned()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn body_hash_ignores_key_order_and_whitespace() {
+        let a: Value = serde_json::from_str(
+            r#"{"model":"claude","messages":[{"role":"user","content":"Hi"}]}"#,
+        )
+        .unwrap();
+        let b: Value = serde_json::from_str(
+            r#"{ "messages": [ { "content": "Hi", "role": "user" } ], "model": "claude" }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            body_hash(&canonical(&a)).unwrap(),
+            body_hash(&canonical(&b)).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&canonical(&b)).unwrap(),
+            r#"{"messages":[{"content":"Hi","role":"user"}],"model":"claude"}"#
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use zeroize::Zeroize;
+
+use crate::secret::SecretKey;
+use crate::Client;
+
+/// Client settings from a profile in the config file or from `ANTHROPIC_*`
+/// environment variables. Timeouts are in seconds.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Profile {
+    api_key: Option<SecretKey>,
+    /// The environment variable to read the key from, to keep it out of the file.
+    api_key_env: Option<String>,
+    base_url: Option<String>,
+    version: Option<String>,
+    beta: Option<String>,
+    model: Option<String>,
+    max_tokens: Option<u32>,
+    max_retries: Option<u32>,
+    connect_timeout: Option<f64>,
+    first_byte_timeout: Option<f64>,
+    idle_timeout: Option<f64>,
+    timeout: Option<f64>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    default_profile: Option<String>,
+    #[serde(default)]
+    profiles: HashMap<String, Profile>,
+}
+
+/// `$ANTHROPIC_CONFIG`, or `anthropic/config.toml` in `$XDG_CONFIG_HOME` or
+/// `~/.config`.
+pub(crate) fn default_path() -> Result<PathBuf> {
+    if let Some(path) = env("ANTHROPIC_CONFIG") {
+        return Ok(path.into());
+    }
+    let config_home = env("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env("HOME").map(|home| Path::new(&home).join(".config")))
+        .context("Cannot locate the config file: neither XDG_CONFIG_HOME nor HOME is set")?;
+    Ok(config_home.join("anthropic").join("config.toml"))
+}
+
+impl Profile {
+    /// The profile called `name` in the file at `path`, or its `default_profile`.
+    pub fn load(path: &Path, name: Option<&str>) -> Result<Self> {
+        let mut text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let file = toml::from_str::<ConfigFile>(&text);
+        // The file may hold API keys.
+        text.zeroize();
+        let mut file = file.with_context(|| format!("Invalid config file {}", path.display()))?;
+
+        let name = name
+            .map(str::to_owned)
+            .or(file.default_profile)
+            .unwrap_or_else(|| "default".to_owned());
+        file.profiles
+            .remove(&name)
+            .ok_or_else(|| anyhow!("No profile {name} in config file {}", path.display()))
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {
+            api_key: env("ANTHROPIC_API_KEY").map(SecretKey::from),
+            api_key_env: None,
+            base_url: env("ANTHROPIC_BASE_URL"),
+            version: env("ANTHROPIC_VERSION"),
+            beta: env("ANTHROPIC_BETA"),
+            model: None,
+            max_tokens: None,
+            max_retries: parse_env("ANTHROPIC_MAX_RETRIES")?,
+            connect_timeout: parse_env("ANTHROPIC_CONNECT_TIMEOUT")?,
+            first_byte_timeout: parse_env("ANTHROPIC_FIRST_BYTE_TIMEOUT")?,
+            idle_timeout: parse_env("ANTHROPIC_IDLE_TIMEOUT")?,
+            timeout: parse_env("ANTHROPIC_TIMEOUT")?,
+        })
+    }
+
+    /// Fills the settings missing here from `fallback`.
+    pub fn or(self, fallback: Profile) -> Self {
+        Self {
+            api_key: self.api_key.or(fallback.api_key),
+            api_key_env: self.api_key_env.or(fallback.api_key_env),
+            base_url: self.base_url.or(fallback.base_url),
+            version: self.version.or(fallback.version),
+            beta: self.beta.or(fallback.beta),
+            model: self.model.or(fallback.model),
+            max_tokens: self.max_tokens.or(fallback.max_tokens),
+            max_retries: self.max_retries.or(fallback.max_retries),
+            connect_timeout: self.connect_timeout.or(fallback.connect_timeout),
+            first_byte_timeout: self.first_byte_timeout.or(fallback.first_byte_timeout),
+            idle_timeout: self.idle_timeout.or(fallback.idle_timeout),
+            timeout: self.timeout.or(fallback.timeout),
+        }
+    }
+
+    pub fn into_client(self) -> Result<Client> {
+        let api_key = match (self.api_key, &self.api_key_env) {
+            (Some(api_key), _) => api_key,
+            (None, Some(variable)) => env(variable)
+                .map(SecretKey::from)
+                .with_context(|| format!("{variable} is not set"))?,
+            (None, None) => env("ANTHROPIC_API_KEY")
+                .map(SecretKey::from)
+                .context("ANTHROPIC_API_KEY is not set")?,
+        };
+
+        let mut client = Client::new().secret_key(api_key);
+        if let Some(base_url) = &self.base_url {
+            client = client.base_url(base_url);
+        }
+        if let Some(version) = &self.version {
+            client = client.version(version);
+        }
+        if let Some(betas) = &self.beta {
+            client = client.betas(
+                betas
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|beta| !beta.is_empty()),
+            );
+        }
+        if let Some(model) = &self.model {
+            client = client.model(model);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            client = client.max_tokens(max_tokens);
+        }
+        if let Some(max_retries) = self.max_retries {
+            client = client.max_retries(max_retries);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            client = client.connect_timeout(seconds(timeout)?);
+        }
+        if let Some(timeout) = self.first_byte_timeout {
+            client = client.first_byte_timeout(seconds(timeout)?);
+        }
+        if let Some(timeout) = self.idle_timeout {
+            client = client.idle_timeout(seconds(timeout)?);
+        }
+        if let Some(timeout) = self.timeout {
+            client = client.total_timeout(seconds(timeout)?);
+        }
+        Ok(client)
+    }
+}
+
+/// A set, non-empty environment variable.
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+fn parse_env<T>(name: &str) -> Result<Option<T>>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    env(name)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid {name}: {value}"))
+        })
+        .transpose()
+}
+
+fn seconds(timeout: f64) -> Result<Duration> {
+    if !timeout.is_finite() || timeout < 0.0 {
+        bail!("Invalid timeout of {timeout} seconds");
+    }
+    Ok(Duration::from_secs_f64(timeout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_keys_are_secret() {
+        let profile: Profile = toml::from_str("api_key = \"sk-ant-file-key\"").unwrap();
+        let api_key = profile.api_key.unwrap();
+
+        assert_eq!(api_key.expose(), "sk-ant-file-key");
+        assert_eq!(format!("{api_key:?}"), "sk-ant-…****");
+    }
+}
@@ -0,0 +1,589 @@
+use std::fmt;
+use std::ops::Range;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{AnthropicResponse, ApiError, Connection, ContentItem, MESSAGES_PATH};
+
+const COUNT_TOKENS_PATH: &str = "/v1/messages/count_tokens";
+const COUNT_TOKENS_FIELDS: [&str; 6] = [
+    "model",
+    "messages",
+    "system",
+    "tools",
+    "tool_choice",
+    "thinking",
+];
+/// Characters of serialized JSON per token assumed by [`TokenCounter::estimate`].
+const DEFAULT_CHARS_PER_TOKEN: f32 = 3.5;
+const SUMMARY_SYSTEM_PROMPT: &str = "Summarize the conversation below so it can replace the \
+original messages. Keep names, decisions, open questions and any facts the assistant will need \
+to continue. Reply with the summary only.";
+
+#[derive(Deserialize)]
+struct CountTokensResponse {
+    input_tokens: u32,
+}
+
+/// How a [`ContextPolicy`] measures the size of a request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenCounter {
+    /// Exact counts from the count_tokens endpoint, at the cost of an extra round trip.
+    CountTokens,
+    /// Local estimate assuming `chars_per_token` characters of serialized JSON per token.
+    Estimate { chars_per_token: f32 },
+}
+
+impl TokenCounter {
+    pub fn estimate() -> Self {
+        Self::Estimate {
+            chars_per_token: DEFAULT_CHARS_PER_TOKEN,
+        }
+    }
+}
+
+/// A pluggable way of shrinking a conversation that no longer fits.
+#[async_trait]
+pub trait TruncationStrategy: Send + Sync {
+    async fn truncate(
+        &self,
+        messages: Vec<Value>,
+        window: &ContextWindow<'_>,
+    ) -> Result<Vec<Value>>;
+}
+
+/// Checks the request size before every send and hands oversized conversations
+/// to a [`TruncationStrategy`]. `max_tokens` is reserved out of `context_window`.
+#[derive(Clone)]
+pub struct ContextPolicy {
+    context_window: u32,
+    counter: TokenCounter,
+    strategy: Arc<dyn TruncationStrategy>,
+}
+
+impl fmt::Debug for ContextPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ContextPolicy")
+            .field("context_window", &self.context_window)
+            .field("counter", &self.counter)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ContextPolicy {
+    pub fn new<S: TruncationStrategy + 'static>(context_window: u32, strategy: S) -> Self {
+        Self {
+            context_window,
+            counter: TokenCounter::estimate(),
+            strategy: Arc::new(strategy),
+        }
+    }
+
+    pub fn counter(mut self, counter: TokenCounter) -> Self {
+        self.counter = counter;
+        self
+    }
+
+    pub(crate) async fn apply(&self, connection: &Connection, body: &mut Value) -> Result<()> {
+        let max_tokens = body["max_tokens"].as_u64().unwrap_or_default() as u32;
+        let window = ContextWindow {
+            connection,
+            body,
+            counter: self.counter,
+            max_input_tokens: self.context_window.saturating_sub(max_tokens),
+        };
+
+        let messages = body["messages"].as_array().cloned().unwrap_or_default();
+        if window.fits(&messages).await? {
+            return Ok(());
+        }
+
+        let messages = self.strategy.truncate(messages, &window).await?;
+        if !window.fits(&messages).await? {
+            return Err(anyhow!(
+                "Conversation does not fit in the {} token context window after truncation",
+                self.context_window
+            ));
+        }
+
+        body["messages"] = Value::Array(messages);
+        Ok(())
+    }
+}
+
+/// The request being checked, as seen by a [`TruncationStrategy`].
+pub struct ContextWindow<'a> {
+    connection: &'a Connection,
+    body: &'a Value,
+    counter: TokenCounter,
+    max_input_tokens: u32,
+}
+
+impl ContextWindow<'_> {
+    /// Splits `messages` into turns. A turn starts at a user message that is not a
+    /// tool result, so a tool_use and its tool_result always land in the same turn.
+    pub fn turns(messages: &[Value]) -> Vec<Range<usize>> {
+        let starts: Vec<usize> = messages
+            .iter()
+            .enumerate()
+            .filter(|(index, message)| {
+                *index == 0 || (message["role"] == "user" && !has_tool_result(message))
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        starts
+            .iter()
+            .enumerate()
+            .map(|(n, &start)| start..starts.get(n + 1).copied().unwrap_or(messages.len()))
+            .collect()
+    }
+
+    pub fn max_input_tokens(&self) -> u32 {
+        self.max_input_tokens
+    }
+
+    /// Counts the input tokens of the request with its messages replaced by `messages`.
+    pub async fn count(&self, messages: &[Value]) -> Result<u32> {
+        let mut body = self.body.clone();
+        body["messages"] = Value::Array(messages.to_vec());
+
+        match self.counter {
+            TokenCounter::CountTokens => count_tokens(self.connection, &body).await,
+            TokenCounter::Estimate { chars_per_token } => {
+                Ok(estimate_tokens(&body, chars_per_token))
+            }
+        }
+    }
+
+    pub async fn fits(&self, messages: &[Value]) -> Result<bool> {
+        Ok(self.count(messages).await? <= self.max_input_tokens)
+    }
+
+    /// Sends a one-off, non-streaming request with the same credentials, using the
+    /// request's own model unless `model` is given, and returns the reply text.
+    pub async fn complete(
+        &self,
+        model: Option<&str>,
+        system: &str,
+        prompt: &str,
+        max_tokens: u32,
+    ) -> Result<String> {
+        let body = json!({
+            "model": model.map(Value::from).unwrap_or_else(|| self.body["model"].clone()),
+            "max_tokens": max_tokens,
+            "system": system,
+            "messages": [{"role": "user", "content": prompt}],
+        });
+
+        let response = self
+            .connection
+            .send(MESSAGES_PATH, &body)
+            .await
+            .context("Failed to send request")?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let json_text = response
+            .text()
+            .await
+            .context("Failed to read response text")?;
+        if !status.is_success() {
+            return Err(ApiError::new(status, &headers, &json_text).into());
+        }
+
+        let anthropic_response: AnthropicResponse = serde_json::from_str(&json_text)
+            .context("Failed to parse response as AnthropicResponse")?;
+        Ok(anthropic_response
+            .content
+            .iter()
+            .filter_map(|item| match item {
+                ContentItem::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+/// Drops whole turns from the start of the conversation until it fits. The last
+/// turn is always kept.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DropOldest;
+
+#[async_trait]
+impl TruncationStrategy for DropOldest {
+    async fn truncate(
+        &self,
+        messages: Vec<Value>,
+        window: &ContextWindow<'_>,
+    ) -> Result<Vec<Value>> {
+        let turns = ContextWindow::turns(&messages);
+        if turns.len() <= 1 {
+            return Ok(messages);
+        }
+
+        // Binary search keeps the number of count_tokens calls logarithmic.
+        let (mut low, mut high) = (1, turns.len() - 1);
+        while low < high {
+            let mid = (low + high) / 2;
+            if window.fits(&messages[turns[mid].start..]).await? {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+
+        Ok(messages[turns[low].start..].to_vec())
+    }
+}
+
+/// Keeps the first `first` and the last `last` turns and drops everything between.
+#[derive(Debug, Clone, Copy)]
+pub struct KeepEnds {
+    pub first: usize,
+    pub last: usize,
+}
+
+#[async_trait]
+impl TruncationStrategy for KeepEnds {
+    async fn truncate(
+        &self,
+        messages: Vec<Value>,
+        _window: &ContextWindow<'_>,
+    ) -> Result<Vec<Value>> {
+        let turns = ContextWindow::turns(&messages);
+        let last = self.last.max(1);
+        if turns.len() <= self.first + last {
+            return Ok(messages);
+        }
+
+        let head_end = turns[self.first].start;
+        let tail_start = turns[turns.len() - last].start;
+        let mut kept = messages[..head_end].to_vec();
+        kept.extend_from_slice(&messages[tail_start..]);
+        Ok(kept)
+    }
+}
+
+/// Replaces everything but the last `keep_last` turns with a model-generated summary,
+/// which is prepended to the first kept user message.
+#[derive(Debug, Clone)]
+pub struct Summarize {
+    keep_last: usize,
+    model: Option<String>,
+    max_tokens: u32,
+}
+
+impl Summarize {
+    pub fn new(keep_last: usize) -> Self {
+        Self {
+            keep_last,
+            model: None,
+            max_tokens: 1024,
+        }
+    }
+
+    /// Uses `model` for summaries instead of the model of the request being trimmed.
+    pub fn model(mut self, model: &str) -> Self {
+        self.model = Some(model.to_owned());
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+}
+
+#[async_trait]
+impl TruncationStrategy for Summarize {
+    async fn truncate(
+        &self,
+        messages: Vec<Value>,
+        window: &ContextWindow<'_>,
+    ) -> Result<Vec<Value>> {
+        let turns = ContextWindow::turns(&messages);
+        let keep_last = self.keep_last.max(1);
+        if turns.len() <= keep_last {
+            return Ok(messages);
+        }
+
+        let split = turns[turns.len() - keep_last].start;
+        let summary = window
+            .complete(
+                self.model.as_deref(),
+                SUMMARY_SYSTEM_PROMPT,
+                &render_transcript(&messages[..split]),
+                self.max_tokens,
+            )
+            .await?;
+
+        let mut kept = messages[split..].to_vec();
+        prepend_text(
+            &mut kept[0],
+            format!("Summary of the earlier conversation:\n{summary}"),
+        );
+        Ok(kept)
+    }
+}
+
+pub(crate) async fn count_tokens(connection: &Connection, body: &Value) -> Result<u32> {
+    let count_body: serde_json::Map<String, Value> = COUNT_TOKENS_FIELDS
+        .iter()
+        .filter_map(|field| {
+            body.get(*field)
+                .map(|value| (field.to_string(), value.clone()))
+        })
+        .collect();
+
+    let response = connection
+        .send(COUNT_TOKENS_PATH, &Value::Object(count_body))
+        .await
+        .context("Failed to send count_tokens request")?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let json_text = response
+        .text()
+        .await
+        .context("Failed to read response text")?;
+    if !status.is_success() {
+        return Err(ApiError::new(status, &headers, &json_text).into());
+    }
+
+    let counted: CountTokensResponse =
+        serde_json::from_str(&json_text).context("Failed to parse count_tokens response")?;
+    Ok(counted.input_tokens)
+}
+
+/// Local estimate of the input tokens of `body`, for callers that cannot wait on count_tokens.
+pub(crate) fn estimate_input_tokens(body: &Value) -> u32 {
+    estimate_tokens(body, DEFAULT_CHARS_PER_TOKEN)
+}
+
+fn estimate_tokens(body: &Value, chars_per_token: f32) -> u32 {
+    let chars: usize = COUNT_TOKENS_FIELDS
+        .iter()
+        .filter(|field| **field != "model")
+        .filter_map(|field| body.get(*field))
+        .map(|value| value.to_string().chars().count())
+        .sum();
+    (chars as f32 / chars_per_token.max(0.1)).ceil() as u32
+}
+
+fn has_tool_result(message: &Value) -> bool {
+    message["content"]
+        .as_array()
+        .is_some_and(|blocks| blocks.iter().any(|block| block["type"] == "tool_result"))
+}
+
+fn render_transcript(messages: &[Value]) -> String {
+    let mut transcript = String::new();
+    for message in messages {
+        let role = message["role"].as_str().unwrap_or("user");
+        let text = match &message["content"] {
+            Value::String(text) => text.clone(),
+            Value::Array(blocks) => blocks
+                .iter()
+                .map(|block| match block["text"].as_str() {
+                    Some(text) => text.to_owned(),
+                    None => block.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            other => other.to_string(),
+        };
+        transcript.push_str(&format!("{role}: {text}\n\n"));
+    }
+    transcript
+}
+
+fn prepend_text(message: &mut Value, text: String) {
+    let block = json!({"type": "text", "text": text});
+    match &mut message["content"] {
+        Value::Array(blocks) => blocks.insert(0, block),
+        content => {
+            let original = content.take();
+            *content = json!([block, {"type": "text", "text": original}]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Client, MockResponse, MockTransport};
+
+    fn user(text: &str) -> Value {
+        json!({"role": "user", "content": text})
+    }
+
+    fn assistant(text: &str) -> Value {
+        json!({"role": "assistant", "content": text})
+    }
+
+    /// `count` turns of one user and one assistant message each.
+    fn conversation(count: usize) -> Vec<Value> {
+        (0..count)
+            .flat_map(|turn| {
+                [
+                    user(&format!("question {turn}")),
+                    assistant(&format!("answer {turn}")),
+                ]
+            })
+            .collect()
+    }
+
+    fn request(messages: &[Value]) -> Value {
+        json!({"model": "claude-3-opus-20240229", "max_tokens": 0, "messages": messages})
+    }
+
+    fn window<'a>(
+        connection: &'a Connection,
+        body: &'a Value,
+        max_input_tokens: u32,
+    ) -> ContextWindow<'a> {
+        ContextWindow {
+            connection,
+            body,
+            counter: TokenCounter::Estimate {
+                chars_per_token: 1.0,
+            },
+            max_input_tokens,
+        }
+    }
+
+    #[test]
+    fn tool_use_and_tool_result_share_a_turn() {
+        let messages = [
+            user("What's the weather?"),
+            json!({"role": "assistant", "content": [
+                {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {}}
+            ]}),
+            json!({"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "toolu_1", "content": "Sunny"}
+            ]}),
+            assistant("It's sunny."),
+            user("Thanks"),
+            assistant("You're welcome."),
+        ];
+
+        assert_eq!(ContextWindow::turns(&messages), vec![0..4, 4..6]);
+        assert_eq!(ContextWindow::turns(&messages[1..]), vec![0..3, 3..5]);
+        assert!(ContextWindow::turns(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_keeps_the_most_turns_that_fit() {
+        let messages = conversation(8);
+        let body = request(&messages);
+        let connection = Client::new().connection(&body);
+        let turns = ContextWindow::turns(&messages);
+
+        for kept in 1..turns.len() {
+            let start = turns[turns.len() - kept].start;
+            let limit = estimate_tokens(&request(&messages[start..]), 1.0);
+            let truncated = DropOldest
+                .truncate(messages.clone(), &window(&connection, &body, limit))
+                .await
+                .unwrap();
+            assert_eq!(truncated, messages[start..], "keeping {kept} turns");
+        }
+    }
+
+    #[tokio::test]
+    async fn keep_ends_drops_the_middle_turns() {
+        let messages = conversation(5);
+        let body = request(&messages);
+        let connection = Client::new().connection(&body);
+        let window = window(&connection, &body, 0);
+        let mut ends = messages[..2].to_vec();
+        ends.extend_from_slice(&messages[6..]);
+
+        // The last turn is always kept, and nothing is dropped once the ends
+        // cover every turn.
+        let cases = [
+            (1, 2, ends),
+            (0, 1, messages[8..].to_vec()),
+            (0, 0, messages[8..].to_vec()),
+            (4, 0, messages.clone()),
+            (2, 3, messages.clone()),
+            (3, 3, messages.clone()),
+        ];
+        for (first, last, expected) in cases {
+            let kept = KeepEnds { first, last }
+                .truncate(messages.clone(), &window)
+                .await
+                .unwrap();
+            assert_eq!(kept, expected, "first {first}, last {last}");
+        }
+    }
+
+    #[tokio::test]
+    async fn summarize_prepends_the_summary_to_string_and_array_content() {
+        let last_turns = [
+            user("question 2"),
+            json!({"role": "user", "content": [{"type": "text", "text": "question 2"}]}),
+        ];
+        for last in last_turns {
+            let mut messages = conversation(2);
+            messages.extend([last, assistant("answer 2")]);
+            let body = request(&messages);
+            let transport = MockTransport::new().reply(MockResponse::message("They said hi."));
+            let connection = Client::new().transport(transport.clone()).connection(&body);
+
+            let truncated = Summarize::new(1)
+                .model("claude-3-haiku-20240307")
+                .truncate(messages.clone(), &window(&connection, &body, 0))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                truncated,
+                [
+                    json!({"role": "user", "content": [
+                        {"type": "text", "text": "Summary of the earlier conversation:\nThey said hi."},
+                        {"type": "text", "text": "question 2"},
+                    ]}),
+                    assistant("answer 2"),
+                ]
+            );
+            let requests = transport.requests();
+            assert_eq!(requests.len(), 1);
+            assert_eq!(requests[0].body["model"], "claude-3-haiku-20240307");
+            assert!(requests[0].body["messages"][0]["content"]
+                .as_str()
+                .unwrap()
+                .contains("assistant: answer 1"));
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_conversation_does_not_fit_after_truncation() {
+        let mut body = request(&conversation(3));
+        body["max_tokens"] = json!(100);
+        let original = body.clone();
+        let connection = Client::new().connection(&body);
+
+        let error = ContextPolicy::new(110, DropOldest)
+            .apply(&connection, &mut body)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Conversation does not fit in the 110 token context window after truncation"
+        );
+        assert_eq!(body, original);
+    }
+
+    #[test]
+    fn estimates_without_the_model_field() {
+        let body = json!({"model": "a-very-long-model-name", "messages": "1234567"});
+
+        assert_eq!(estimate_tokens(&body, 1.0), 9);
+        assert_eq!(estimate_input_tokens(&body), 3);
+    }
+}
@@ -0,0 +1,347 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{AnthropicResponse, Client, Usage};
+
+/// Format version written into saved conversations and transcript entries.
+pub const CONVERSATION_VERSION: u32 = 1;
+
+/// A chat session that can be saved to disk and restored later.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    pub version: u32,
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub messages: Vec<Value>,
+}
+
+impl Conversation {
+    pub fn new(model: &str) -> Self {
+        Self {
+            version: CONVERSATION_VERSION,
+            model: model.to_owned(),
+            system: None,
+            messages: Vec::new(),
+        }
+    }
+
+    pub fn system(mut self, system: &str) -> Self {
+        self.system = Some(system.to_owned());
+        self
+    }
+
+    pub fn push_message(&mut self, message: Value) {
+        self.messages.push(message);
+    }
+
+    pub fn push_user(&mut self, content: &str) {
+        self.push_message(json!({"role": "user", "content": content}));
+    }
+
+    pub fn push_response(&mut self, response: &AnthropicResponse) {
+        self.push_message(response_message(response));
+    }
+
+    /// Copies the model, system prompt and messages onto `client`.
+    pub fn apply(&self, client: Client) -> Client {
+        let client = client
+            .model(&self.model)
+            .messages(&Value::Array(self.messages.clone()));
+        match &self.system {
+            Some(system) => client.system(system),
+            None => client,
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).context("Failed to serialize conversation")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let conversation: Conversation =
+            serde_json::from_str(json).context("Failed to parse conversation")?;
+        check_version(conversation.version)?;
+        Ok(conversation)
+    }
+
+    /// Writes the conversation to `path`, replacing any previous save atomically.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp_path = temp_file_for(path)?;
+        fs::write(&tmp_path, self.to_json()?)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::from_json(&json)
+    }
+
+    /// Rebuilds a conversation from the messages recorded in a JSONL transcript.
+    pub fn from_transcript<P: AsRef<Path>>(model: &str, path: P) -> Result<Self> {
+        let mut conversation = Self::new(model);
+        for entry in Transcript::read(path)? {
+            conversation.push_message(entry.message);
+        }
+        Ok(conversation)
+    }
+}
+
+/// One line of a JSONL transcript.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    pub version: u32,
+    pub timestamp: u64,
+    pub message: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+}
+
+/// Append-only JSONL transcript. Every entry is flushed as it is written so the
+/// file can be tailed while the session is running.
+pub struct Transcript {
+    file: File,
+}
+
+impl Transcript {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        Ok(Self { file })
+    }
+
+    pub fn append(&mut self, message: &Value) -> Result<()> {
+        self.write_entry(message.clone(), None)
+    }
+
+    pub fn append_response(&mut self, response: &AnthropicResponse) -> Result<()> {
+        self.write_entry(response_message(response), Some(response.usage.clone()))
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Vec<TranscriptEntry>> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut entries = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.with_context(|| format!("Failed to read {}", path.display()))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: TranscriptEntry = serde_json::from_str(&line).with_context(|| {
+                format!("Failed to parse line {} of {}", number + 1, path.display())
+            })?;
+            check_version(entry.version)?;
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    fn write_entry(&mut self, message: Value, usage: Option<Usage>) -> Result<()> {
+        let entry = TranscriptEntry {
+            version: CONVERSATION_VERSION,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or_default(),
+            message,
+            usage,
+        };
+        let mut line = serde_json::to_string(&entry).context("Failed to serialize entry")?;
+        line.push('\n');
+        self.file
+            .write_all(line.as_bytes())
+            .context("Failed to append transcript entry")?;
+        self.file.flush().context("Failed to flush transcript")?;
+        Ok(())
+    }
+}
+
+/// A temporary file next to `path` that no other save writes to at the same time.
+fn temp_file_for(path: &Path) -> Result<PathBuf> {
+    static SAVES: AtomicU64 = AtomicU64::new(0);
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("Cannot save to {}", path.display()))?;
+    Ok(path.with_file_name(format!(
+        "{}.{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        SAVES.fetch_add(1, Ordering::Relaxed)
+    )))
+}
+
+fn response_message(response: &AnthropicResponse) -> Value {
+    json!({"role": response.role, "content": response.content})
+}
+
+fn check_version(version: u32) -> Result<()> {
+    if version > CONVERSATION_VERSION {
+        return Err(anyhow!(
+            "Unsupported conversation version {} (expected at most {})",
+            version,
+            CONVERSATION_VERSION
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ContentItem;
+
+    /// A path in the temp directory unique to this process and `name`.
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("anthropic-sdk-{}-{name}", std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn response(text: &str) -> AnthropicResponse {
+        AnthropicResponse {
+            id: "msg_1".to_owned(),
+            model: "claude-3-opus-20240229".to_owned(),
+            stop_reason: "end_turn".to_owned(),
+            role: "assistant".to_owned(),
+            content: vec![ContentItem::Text {
+                text: text.to_owned(),
+            }],
+            usage: Usage {
+                input_tokens: 5,
+                output_tokens: 1,
+                ..Usage::default()
+            },
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_path("conversation.json");
+        let mut conversation = Conversation::new("claude-3-opus-20240229").system("Be brief");
+        conversation.push_user("Hello");
+        conversation.push_response(&response("Hi"));
+
+        conversation.save(&path).unwrap();
+        let loaded = Conversation::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.version, CONVERSATION_VERSION);
+        assert_eq!(loaded.model, "claude-3-opus-20240229");
+        assert_eq!(loaded.system.as_deref(), Some("Be brief"));
+        assert_eq!(
+            loaded.messages,
+            [
+                json!({"role": "user", "content": "Hello"}),
+                json!({"role": "assistant", "content": [{"type": "text", "text": "Hi"}]}),
+            ]
+        );
+        let prefix = format!("{}.", path.file_name().unwrap().to_string_lossy());
+        assert!(!fs::read_dir(path.parent().unwrap()).unwrap().any(|entry| {
+            let name = entry.unwrap().file_name().to_string_lossy().into_owned();
+            name.starts_with(&prefix) && name.ends_with(".tmp")
+        }));
+    }
+
+    #[test]
+    fn saves_use_their_own_temp_file() {
+        let path = temp_path("conversation.tmp");
+        let first = temp_file_for(&path).unwrap();
+        let second = temp_file_for(&path).unwrap();
+
+        assert_ne!(first, path);
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), path.parent());
+        assert!(first.to_string_lossy().ends_with(".tmp"));
+
+        Conversation::new("claude-3-opus-20240229")
+            .save(&path)
+            .unwrap();
+        let loaded = Conversation::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.model, "claude-3-opus-20240229");
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let json = json!({
+            "version": CONVERSATION_VERSION + 1,
+            "model": "claude-3-opus-20240229",
+            "messages": [],
+        });
+
+        let error = Conversation::from_json(&json.to_string()).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Unsupported conversation version {} (expected at most {CONVERSATION_VERSION})",
+                CONVERSATION_VERSION + 1
+            )
+        );
+    }
+
+    #[test]
+    fn transcript_appends_one_line_per_entry() {
+        let path = temp_path("transcript.jsonl");
+        let mut transcript = Transcript::open(&path).unwrap();
+        transcript
+            .append(&json!({"role": "user", "content": "Hello"}))
+            .unwrap();
+        transcript.append_response(&response("Hi")).unwrap();
+        drop(transcript);
+        Transcript::open(&path)
+            .unwrap()
+            .append(&json!({"role": "user", "content": "Bye"}))
+            .unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let entries = Transcript::read(&path).unwrap();
+        let conversation = Conversation::from_transcript("claude-3-opus-20240229", &path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(text.lines().count(), 3);
+        assert!(text.ends_with('\n'));
+        assert_eq!(entries.len(), 3);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.version == CONVERSATION_VERSION));
+        assert!(entries[0].usage.is_none());
+        assert_eq!(entries[1].usage.as_ref().unwrap().output_tokens, 1);
+        assert_eq!(conversation.messages[2]["content"], "Bye");
+    }
+
+    #[test]
+    fn transcript_rejects_newer_versions() {
+        let path = temp_path("future.jsonl");
+        let entry = json!({
+            "version": CONVERSATION_VERSION + 1,
+            "timestamp": 0,
+            "message": {"role": "user", "content": "Hello"},
+        });
+        fs::write(&path, format!("{entry}\n")).unwrap();
+
+        let result = Transcript::read(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+}
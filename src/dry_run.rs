@@ -0,0 +1,31 @@
+use serde_json::Value;
+
+/// A request as `Request::prepare` would send it, for a dry run.
+#[derive(Debug, Clone)]
+pub struct PreparedRequest {
+    pub url: String,
+    pub body: Value,
+    /// The request as a curl command, with the API key read from `$ANTHROPIC_API_KEY`.
+    pub curl: String,
+}
+
+/// A POST of `body` to `url` as a shell command. The `x-api-key` header refers to
+/// `$ANTHROPIC_API_KEY` instead of holding the key.
+pub(crate) fn curl(url: &str, headers: &[(&str, &str)], body: &Value) -> String {
+    let mut command = format!("curl {}", quote(url));
+    for (name, value) in headers {
+        let header = if name.eq_ignore_ascii_case("x-api-key") {
+            format!("\"{name}: $ANTHROPIC_API_KEY\"")
+        } else {
+            quote(&format!("{name}: {value}"))
+        };
+        command.push_str(&format!(" \\\n  -H {header}"));
+    }
+    command.push_str(&format!(" \\\n  -d {}", quote(&body.to_string())));
+    command
+}
+
+/// Single-quotes `text` for a POSIX shell.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
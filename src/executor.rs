@@ -0,0 +1,120 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use futures::stream::{self, BoxStream, StreamExt};
+
+use crate::{AnthropicResponse, Request, Response};
+
+/// Running totals reported to the [`RunOptions::on_progress`] callback.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub completed: usize,
+    pub failed: usize,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+type ProgressCallback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Options for [`run_many_with`].
+#[derive(Clone)]
+pub struct RunOptions {
+    concurrency: usize,
+    preserve_order: bool,
+    on_progress: Option<ProgressCallback>,
+}
+
+impl fmt::Debug for RunOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RunOptions")
+            .field("concurrency", &self.concurrency)
+            .field("preserve_order", &self.preserve_order)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RunOptions {
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            concurrency: concurrency.max(1),
+            preserve_order: false,
+            on_progress: None,
+        }
+    }
+
+    /// Yields results in input order. A slow request then holds back the ones after it.
+    pub fn preserve_order(mut self, preserve_order: bool) -> Self {
+        self.preserve_order = preserve_order;
+        self
+    }
+
+    /// Called after every request finishes, successfully or not.
+    pub fn on_progress<F>(mut self, on_progress: F) -> Self
+    where
+        F: Fn(&Progress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+}
+
+/// Sends `requests` with at most `concurrency` in flight, yielding
+/// `(index, result)` pairs as they finish.
+pub fn run_many<I>(
+    requests: I,
+    concurrency: usize,
+) -> BoxStream<'static, (usize, Result<Response<AnthropicResponse>>)>
+where
+    I: IntoIterator<Item = Request>,
+    I::IntoIter: Send + 'static,
+{
+    run_many_with(requests, RunOptions::new(concurrency))
+}
+
+/// Like [`run_many`], with ordering and progress reporting. Requests are only
+/// pulled from `requests` as slots free up, and each one keeps the retry and
+/// rate-limiter settings of the client that built it.
+pub fn run_many_with<I>(
+    requests: I,
+    options: RunOptions,
+) -> BoxStream<'static, (usize, Result<Response<AnthropicResponse>>)>
+where
+    I: IntoIterator<Item = Request>,
+    I::IntoIter: Send + 'static,
+{
+    let progress = Arc::new(Mutex::new(Progress::default()));
+    let on_progress = options.on_progress.clone();
+
+    let pending = stream::iter(requests.into_iter().enumerate()).map(move |(index, request)| {
+        let progress = progress.clone();
+        let on_progress = on_progress.clone();
+        async move {
+            let result = request.execute_and_return_json().await;
+
+            let snapshot = {
+                let mut progress = progress.lock().unwrap();
+                match &result {
+                    Ok(response) => {
+                        progress.completed += 1;
+                        progress.input_tokens += u64::from(response.usage.input_tokens);
+                        progress.output_tokens += u64::from(response.usage.output_tokens);
+                    }
+                    Err(_) => progress.failed += 1,
+                }
+                *progress
+            };
+            if let Some(on_progress) = &on_progress {
+                on_progress(&snapshot);
+            }
+
+            (index, result)
+        }
+    });
+
+    if options.preserve_order {
+        pending.buffered(options.concurrency).boxed()
+    } else {
+        pending.buffer_unordered(options.concurrency).boxed()
+    }
+}
@@ -1,14 +1,75 @@
 use anyhow::{anyhow, Context, Result};
-use reqwest::{Client as ReqwestClient, Error as ReqwestError, RequestBuilder, StatusCode};
-use serde::Deserialize;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{
+    Client as ReqwestClient, Error as ReqwestError, Method, RequestBuilder, StatusCode, Url,
+};
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use types::AnthropicChatCompletionChunk;
+mod beta;
+mod cassette;
+mod config;
+mod context;
+mod conversation;
+mod dry_run;
+mod executor;
+mod metrics;
+mod middleware;
+#[cfg(feature = "mock-server")]
+mod mock_server;
+mod models;
+#[cfg(feature = "prometheus")]
+mod prometheus;
+mod ratelimit;
+mod response;
+mod secret;
+mod stream;
+mod telemetry;
+mod timeout;
+mod transport;
 mod types;
-use std::collections::HashMap;
+mod usage;
+mod validation;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::types::AnthropicErrorMessage;
+pub use beta::Beta;
+pub use cassette::Cassette;
+pub use context::{
+    ContextPolicy, ContextWindow, DropOldest, KeepEnds, Summarize, TokenCounter, TruncationStrategy,
+};
+pub use conversation::{Conversation, Transcript, TranscriptEntry, CONVERSATION_VERSION};
+pub use dry_run::PreparedRequest;
+pub use executor::{run_many, run_many_with, Progress, RunOptions};
+pub use metrics::{LatencyPercentiles, StreamMetrics};
+pub use middleware::{HttpRequest, Middleware};
+#[cfg(feature = "mock-server")]
+pub use mock_server::{MockServer, ScriptedReply};
+pub use models::Model;
+#[cfg(feature = "prometheus")]
+pub use prometheus::PrometheusMetrics;
+pub use ratelimit::{RateLimiter, RateLimits};
+pub use response::{ApiError, RateLimit, RateLimitInfo, Response};
+pub use stream::{Cancelled, Completion, StreamError};
+pub use timeout::{Timeout, TimeoutPhase};
+pub use tokio_util::sync::CancellationToken;
+pub use transport::{MockResponse, MockTransport, ReqwestTransport, Transport};
+pub use usage::{Budget, BudgetExceeded, Price, PriceTable, UsageRow, UsageTotals, UsageTracker};
+pub use validation::{ValidationError, ValidationIssue};
 
-#[derive(Debug, Deserialize)]
+const API_URL: &str = "https://api.anthropic.com";
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(8);
+const MESSAGES_PATH: &str = "/v1/messages";
+
+use crate::metrics::{MetricsHook, StreamTimer};
+use crate::middleware::MiddlewareStack;
+use crate::secret::SecretKey;
+use crate::stream::SseParser;
+use crate::telemetry::{RequestSpan, Telemetry};
+use crate::timeout::{guard, Deadline, Timeouts};
+use crate::transport::SharedTransport;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AnthropicResponse {
     pub id: String,
     pub model: String,
@@ -18,13 +79,28 @@ pub struct AnthropicResponse {
     pub usage: Usage,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Usage {
     pub input_tokens: u32,
     pub output_tokens: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_tool_use: Option<ServerToolUsage>,
+    /// `standard`, `priority` or `batch`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_tier: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ServerToolUsage {
+    #[serde(default)]
+    pub web_search_requests: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ContentItem {
     #[serde(rename = "text")]
@@ -35,27 +111,48 @@ pub enum ContentItem {
         name: String,
         input: Value,
     },
+    #[serde(rename = "thinking")]
+    Thinking { thinking: String, signature: String },
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking { data: String },
 }
 
 #[derive(Debug, Clone)]
 pub struct Client {
     client: ReqwestClient,
-    secret_key: String,
+    secret_key: SecretKey,
     model: String,
     messages: Value,
-    tools: Value,
-    metadata: Value,
-    max_tokens: i32,
-    stream: bool,
+    tools: Option<Value>,
+    metadata: Option<Value>,
+    context_management: Option<Value>,
+    max_tokens: u32,
+    stream: Option<bool>,
     verbose: bool,
-    temperature: f32,
-    system: String,
+    temperature: Option<f64>,
+    system: Option<String>,
     version: String,
-    stop_sequences: Vec<String>,
-    beta: Option<String>,
+    stop_sequences: Option<Vec<String>>,
+    betas: BTreeSet<Beta>,
     top_k: Option<i32>,
     top_p: Option<f64>,
     tool_choice: Option<Value>,
+    context_policy: Option<ContextPolicy>,
+    validate: bool,
+    base_url: String,
+    max_retries: u32,
+    rate_limiter: Option<RateLimiter>,
+    timeouts: Timeouts,
+    resume_attempts: u32,
+    thinking: Option<u32>,
+    fallback_models: Vec<String>,
+    clamp_max_tokens: bool,
+    usage_tracker: Option<UsageTracker>,
+    usage_tag: Option<String>,
+    telemetry: Telemetry,
+    on_stream_metrics: Option<MetricsHook>,
+    middleware: MiddlewareStack,
+    transport: Option<SharedTransport>,
 }
 
 #[derive(Deserialize)]
@@ -64,37 +161,99 @@ struct JsonResponse {
 }
 
 #[derive(Deserialize)]
-struct Content {
-    #[serde(rename = "type")]
-    content_type: String,
-    text: String,
+#[serde(tag = "type")]
+enum Content {
+    #[serde(rename = "text")]
+    Text { text: String },
+    #[serde(other)]
+    Other,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Client {
     pub fn new() -> Self {
         Self {
             client: ReqwestClient::new(),
-            secret_key: String::new(),
+            secret_key: SecretKey::default(),
             model: String::new(),
             messages: Value::Null,
-            tools: Value::Null,
-            metadata: Value::Null,
+            tools: None,
+            metadata: None,
+            context_management: None,
             max_tokens: 1024,
-            stream: false,
+            stream: None,
             verbose: false,
-            temperature: 0.0,
-            system: String::new(),
+            temperature: None,
+            system: None,
             version: "2023-06-01".to_string(),
-            stop_sequences: Vec::new(),
-            beta: None,
+            stop_sequences: None,
+            betas: BTreeSet::new(),
             top_k: None,
             top_p: None,
             tool_choice: None,
+            context_policy: None,
+            validate: true,
+            base_url: API_URL.to_string(),
+            max_retries: 0,
+            rate_limiter: None,
+            timeouts: Timeouts::default(),
+            resume_attempts: 0,
+            thinking: None,
+            fallback_models: Vec::new(),
+            clamp_max_tokens: false,
+            usage_tracker: None,
+            usage_tag: None,
+            telemetry: Telemetry::default(),
+            on_stream_metrics: None,
+            middleware: MiddlewareStack::default(),
+            transport: None,
+        }
+    }
+
+    /// A client configured from the environment, after loading any `.env` file.
+    /// Reads `ANTHROPIC_API_KEY`, which is required, and the optional
+    /// `ANTHROPIC_BASE_URL`, `ANTHROPIC_VERSION`, `ANTHROPIC_BETA`,
+    /// `ANTHROPIC_MAX_RETRIES` and `ANTHROPIC_{CONNECT,FIRST_BYTE,IDLE}_TIMEOUT`
+    /// and `ANTHROPIC_TIMEOUT` in seconds. When `ANTHROPIC_PROFILE` is set, that
+    /// profile is loaded from the config file first and the variables override it.
+    pub fn from_env() -> Result<Self> {
+        dotenv::dotenv().ok();
+        let mut profile = config::Profile::from_env()?;
+        if let Some(name) = std::env::var("ANTHROPIC_PROFILE")
+            .ok()
+            .filter(|name| !name.is_empty())
+        {
+            profile = profile.or(config::Profile::load(
+                &config::default_path()?,
+                Some(&name),
+            )?);
         }
+        profile.into_client()
+    }
+
+    /// A client configured from the profile `name` in
+    /// `~/.config/anthropic/config.toml`, or the file `ANTHROPIC_CONFIG` points to.
+    pub fn from_profile(name: &str) -> Result<Self> {
+        Self::from_config(config::default_path()?, name)
+    }
+
+    /// A client configured from the profile `name` in the TOML file at `path`.
+    pub fn from_config(path: impl AsRef<std::path::Path>, name: &str) -> Result<Self> {
+        config::Profile::load(path.as_ref(), Some(name))?.into_client()
     }
 
     pub fn auth(mut self, secret_key: &str) -> Self {
-        self.secret_key = secret_key.to_owned();
+        self.secret_key = SecretKey::new(secret_key);
+        self
+    }
+
+    pub(crate) fn secret_key(mut self, secret_key: SecretKey) -> Self {
+        self.secret_key = secret_key;
         self
     }
 
@@ -109,27 +268,44 @@ impl Client {
     }
 
     pub fn tools(mut self, tools: &Value) -> Self {
-        self.tools = tools.clone();
+        self.tools = Some(tools.clone());
+        self
+    }
+
+    /// Asks for tool calls in fewer output tokens, adding
+    /// [`Beta::TokenEfficientTools`].
+    pub fn token_efficient_tools(mut self, enabled: bool) -> Self {
+        if enabled {
+            self.betas.insert(Beta::TokenEfficientTools);
+        } else {
+            self.betas.remove(&Beta::TokenEfficientTools);
+        }
         self
     }
 
     pub fn metadata(mut self, metadata: &Value) -> Self {
-        self.metadata = metadata.clone();
+        self.metadata = Some(metadata.clone());
         self
     }
 
-    pub fn max_tokens(mut self, max_tokens: i32) -> Self {
+    /// Sets the `context_management` field, adding [`Beta::ContextManagement`].
+    pub fn context_management(mut self, context_management: &Value) -> Self {
+        self.context_management = Some(context_management.clone());
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
         self.max_tokens = max_tokens;
         self
     }
 
-    pub fn temperature(mut self, temperature: f32) -> Self {
-        self.temperature = temperature.to_owned();
+    pub fn temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
         self
     }
 
     pub fn system(mut self, system: &str) -> Self {
-        self.system = system.to_owned();
+        self.system = Some(system.to_owned());
         self
     }
     pub fn version(mut self, version: &str) -> Self {
@@ -138,7 +314,7 @@ impl Client {
     }
 
     pub fn stream(mut self, stream: bool) -> Self {
-        self.stream = stream;
+        self.stream = Some(stream);
         self
     }
 
@@ -147,13 +323,25 @@ impl Client {
         self
     }
 
-    pub fn beta(mut self, beta: &str) -> Self {
-        self.beta = Some(beta.to_owned());
+    /// Enables a beta feature. Betas accumulate and are sent together in the
+    /// `anthropic-beta` header, along with any the request body needs, such as
+    /// [`Beta::FilesApi`] for messages that reference uploaded files.
+    pub fn beta(mut self, beta: impl Into<Beta>) -> Self {
+        self.betas.insert(beta.into());
+        self
+    }
+
+    pub fn betas<I>(mut self, betas: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Beta>,
+    {
+        self.betas.extend(betas.into_iter().map(Into::into));
         self
     }
 
     pub fn stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
-        self.stop_sequences = stop_sequences;
+        self.stop_sequences = Some(stop_sequences);
         self
     }
 
@@ -172,85 +360,269 @@ impl Client {
         self
     }
 
-    pub fn build(self) -> Result<Request, ReqwestError> {
-        let mut body_map: HashMap<&str, Value> = HashMap::new();
-        body_map.insert("model", json!(self.model));
-        body_map.insert("max_tokens", json!(self.max_tokens));
-        body_map.insert("messages", json!(self.messages));
-        body_map.insert("stream", json!(self.stream));
-        body_map.insert("temperature", json!(self.temperature));
-        body_map.insert(
-            "system",
-            json!([{
-                "type": "text",
-                "text": self.system,
-                "cache_control": {"type": "ephemeral"}
-            }]),
-        );
+    /// Enables extended thinking with up to `budget_tokens` of reasoning, which
+    /// must be at least 1024 and below `max_tokens`.
+    pub fn thinking(mut self, budget_tokens: u32) -> Self {
+        self.thinking = Some(budget_tokens);
+        self
+    }
 
-        if let Some(tool_choice) = self.tool_choice {
-            body_map.insert("tool_choice", tool_choice);
-        }
+    /// Runs `policy` before every send to keep the request inside the context window.
+    pub fn context_policy(mut self, policy: ContextPolicy) -> Self {
+        self.context_policy = Some(policy);
+        self
+    }
 
-        if self.tools != Value::Null {
-            body_map.insert("tools", self.tools.clone());
-        }
+    /// Turns the pre-flight validation in `build` on or off. It is on by default.
+    pub fn validate(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
 
-        if self.metadata != Value::Null {
-            body_map.insert("metadata", self.metadata.clone());
-        }
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_owned();
+        self
+    }
 
-        if self.stop_sequences.len() > 0 {
-            body_map.insert("stop_sequences", json!(self.stop_sequences));
-        }
+    /// Retries 408, 409, 429 and 5xx responses and connection errors up to
+    /// `max_retries` times, honouring `retry-after`. Off by default.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
 
-        if let Some(top_k) = self.top_k {
-            body_map.insert("top_k", json!(top_k));
-        }
+    /// Waits for capacity in `rate_limiter` before each send. Clones of a limiter
+    /// share their buckets, so pass the same one to every client using a key.
+    pub fn rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
 
-        if let Some(top_p) = self.top_p {
-            body_map.insert("top_p", json!(top_p));
+    /// Fails with [`TimeoutPhase::Connect`] if a connection can't be opened in time.
+    /// Has no effect on the client inside a custom `transport`.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        // Building only fails if the TLS backend can't start, which `Client::new`
+        // has already done.
+        if let Ok(client) = ReqwestClient::builder().connect_timeout(timeout).build() {
+            self.client = client;
         }
+        self.timeouts.connect = Some(timeout);
+        self
+    }
 
-        let mut request_builder = self
-            .client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", self.secret_key)
-            .header("anthropic-version", self.version)
-            .header("content-type", "application/json")
-            .json(&body_map);
+    /// Fails with [`TimeoutPhase::FirstByte`] if the response headers don't arrive
+    /// in time. Applies to each attempt, so a stalled attempt is retried.
+    pub fn first_byte_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.first_byte = Some(timeout);
+        self
+    }
+
+    /// Fails with [`TimeoutPhase::Idle`] if no stream event, including `ping`,
+    /// arrives in time.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.idle = Some(timeout);
+        self
+    }
 
-        if let Some(beta_value) = self.beta {
-            request_builder = request_builder.header("anthropic-beta", beta_value);
+    /// Fails with [`TimeoutPhase::Total`] if the whole request, retries included,
+    /// takes longer than `timeout`.
+    pub fn total_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.total = Some(timeout);
+        self
+    }
+
+    /// When a stream is cut off by a network error, an idle timeout or a mid-stream
+    /// `overloaded_error`/`api_error`, re-sends the request with the text received so
+    /// far as an assistant prefill and splices the continuation into the same
+    /// callback, up to `max_attempts` times. Off by default.
+    pub fn resume_streams(mut self, max_attempts: u32) -> Self {
+        self.resume_attempts = max_attempts;
+        self
+    }
+
+    /// Models to try in order once retries on the primary model are exhausted by
+    /// overload (529) or other 5xx responses. `build` validates the request against
+    /// each of them, so a fallback that can't serve it is caught before sending.
+    pub fn fallback_models<I, S>(mut self, models: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.fallback_models = models.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Records the usage of every successful request in `tracker`.
+    pub fn usage_tracker(mut self, tracker: UsageTracker) -> Self {
+        self.usage_tracker = Some(tracker);
+        self
+    }
+
+    /// Groups this client's usage under `tag` in the [`UsageTracker`], e.g. by feature.
+    pub fn usage_tag(mut self, tag: &str) -> Self {
+        self.usage_tag = Some(tag.to_owned());
+        self
+    }
+
+    /// Records prompts and completions on `tracing` spans. Off by default, since
+    /// they may hold personal data. Needs the `tracing` feature.
+    pub fn capture_content(mut self, capture_content: bool) -> Self {
+        self.telemetry.capture_content = capture_content;
+        self
+    }
+
+    /// Records request counts, latency, tokens and rate limits in `metrics`.
+    #[cfg(feature = "prometheus")]
+    pub fn prometheus(mut self, metrics: PrometheusMetrics) -> Self {
+        self.telemetry.prometheus = Some(metrics);
+        self
+    }
+
+    /// Called with the [`StreamMetrics`] of every streamed request, for exporting
+    /// them. They are also on the returned `Completion`.
+    pub fn on_stream_metrics<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&StreamMetrics) + Send + Sync + 'static,
+    {
+        self.on_stream_metrics = Some(MetricsHook(Arc::new(hook)));
+        self
+    }
+
+    /// Adds `middleware` to the hooks run around every HTTP call this client makes.
+    pub fn middleware<M: Middleware + 'static>(mut self, middleware: M) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Sends requests through `transport` instead of reqwest, e.g. a
+    /// [`MockTransport`] in tests. Timeouts set on the client then only apply
+    /// where `execute` enforces them itself, unless the transport wraps the
+    /// client's own, as a recording [`Cassette`] does.
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Some(SharedTransport(Arc::new(transport)));
+        self
+    }
+
+    /// Lowers `max_tokens` to the smallest output limit in the [`Model`] catalog
+    /// among the model and its fallbacks, instead of failing validation.
+    pub fn clamp_max_tokens(mut self, clamp: bool) -> Self {
+        self.clamp_max_tokens = clamp;
+        self
+    }
+
+    /// The JSON body `build` would send, pretty-printed. A context policy can
+    /// still change the messages when the request is executed.
+    pub fn to_request_body(&self) -> String {
+        serde_json::to_string_pretty(&self.request_body()).expect("Failed to serialize body")
+    }
+
+    pub fn build(self) -> Result<Request> {
+        let body = self.request_body();
+        if self.validate {
+            let mut issues = validation::validate(&body);
+            for model in &self.fallback_models {
+                let mut fallback = body.clone();
+                fallback["model"] = json!(model);
+                for issue in validation::validate(&fallback) {
+                    if !issues.contains(&issue) {
+                        issues.push(issue);
+                    }
+                }
+            }
+            if !issues.is_empty() {
+                return Err(ValidationError { issues }.into());
+            }
+        }
+        if let Some(tracker) = &self.usage_tracker {
+            tracker.check(&body, self.usage_tag.as_deref())?;
         }
 
         Ok(Request {
-            request_builder,
-            stream: self.stream,
+            connection: self.connection(&body),
+            body,
+            stream: self.stream.unwrap_or(false),
             verbose: self.verbose,
             tools: self.tools,
+            context_policy: self.context_policy,
+            max_retries: self.max_retries,
+            rate_limiter: self.rate_limiter,
+            cancellation: None,
+            timeouts: self.timeouts,
+            resume_attempts: self.resume_attempts,
+            fallback_models: self.fallback_models,
+            usage_tracker: self.usage_tracker,
+            usage_tag: self.usage_tag,
+            telemetry: self.telemetry,
+            on_stream_metrics: self.on_stream_metrics,
         })
     }
 
+    /// The request as a reqwest builder, for sending it yourself. It bypasses
+    /// middleware and any [`Transport`], as well as the validation, retries,
+    /// timeouts and tracking that `build` and `execute` add.
     pub fn builder(self) -> Result<RequestBuilder, ReqwestError> {
+        let body = self.request_body();
+        Ok(self.connection(&body).post(MESSAGES_PATH).json(&body))
+    }
+
+    fn request_body(&self) -> Value {
+        let mut body = self.body();
+        if self.clamp_max_tokens {
+            let limit = std::iter::once(&self.model)
+                .chain(&self.fallback_models)
+                .filter_map(|model| Model::lookup(model))
+                .map(|model| model.max_output_tokens)
+                .min();
+            if let Some(limit) = limit {
+                body["max_tokens"] = json!(self.max_tokens.min(limit));
+            }
+        }
+        body
+    }
+
+    fn body(&self) -> Value {
         let mut body_map: HashMap<&str, Value> = HashMap::new();
         body_map.insert("model", json!(self.model));
         body_map.insert("max_tokens", json!(self.max_tokens));
         body_map.insert("messages", json!(self.messages));
-        body_map.insert("stream", json!(self.stream));
-        body_map.insert("temperature", json!(self.temperature));
-        body_map.insert("system", json!(self.system));
 
-        if self.tools != Value::Null {
-            body_map.insert("tools", self.tools.clone());
+        if let Some(stream) = self.stream {
+            body_map.insert("stream", json!(stream));
+        }
+
+        if let Some(temperature) = self.temperature {
+            body_map.insert("temperature", json!(temperature));
+        }
+
+        if let Some(system) = &self.system {
+            body_map.insert(
+                "system",
+                json!([{
+                    "type": "text",
+                    "text": system,
+                    "cache_control": {"type": "ephemeral"}
+                }]),
+            );
+        }
+
+        if let Some(tool_choice) = &self.tool_choice {
+            body_map.insert("tool_choice", tool_choice.clone());
+        }
+
+        if let Some(tools) = &self.tools {
+            body_map.insert("tools", tools.clone());
+        }
+
+        if let Some(metadata) = &self.metadata {
+            body_map.insert("metadata", metadata.clone());
         }
 
-        if self.metadata != Value::Null {
-            body_map.insert("metadata", self.metadata.clone());
+        if let Some(context_management) = &self.context_management {
+            body_map.insert("context_management", context_management.clone());
         }
 
-        if self.stop_sequences.len() > 0 {
-            body_map.insert("stop_sequences", json!(self.stop_sequences));
+        if let Some(stop_sequences) = &self.stop_sequences {
+            body_map.insert("stop_sequences", json!(stop_sequences));
         }
 
         if let Some(top_k) = self.top_k {
@@ -261,190 +633,643 @@ impl Client {
             body_map.insert("top_p", json!(top_p));
         }
 
-        let mut request_builder = self
-            .client
-            .post("https://api.anthropic.com/v1/messages")
-            .header("x-api-key", self.secret_key)
-            .header("anthropic-version", self.version)
-            .header("content-type", "application/json")
-            .json(&body_map);
+        if let Some(budget_tokens) = self.thinking {
+            body_map.insert(
+                "thinking",
+                json!({"type": "enabled", "budget_tokens": budget_tokens}),
+            );
+        }
+
+        json!(body_map)
+    }
 
-        if let Some(beta_value) = self.beta {
-            request_builder = request_builder.header("anthropic-beta", beta_value);
+    /// The custom transport, wrapped around the reqwest one if it asks to be,
+    /// or the reqwest one alone.
+    fn shared_transport(&self) -> SharedTransport {
+        let inner = ReqwestTransport::new(self.client.clone());
+        match &self.transport {
+            Some(transport) => transport
+                .0
+                .with_inner(inner)
+                .map_or_else(|| transport.clone(), SharedTransport),
+            None => SharedTransport(Arc::new(inner)),
         }
+    }
+
+    fn connection(&self, body: &Value) -> Connection {
+        let mut betas = self.betas.clone();
+        betas.extend(beta::required(body));
+        let beta = betas.iter().map(Beta::as_str).collect::<Vec<_>>().join(",");
 
-        Ok(request_builder)
+        Connection {
+            client: self.client.clone(),
+            secret_key: self.secret_key.clone(),
+            version: self.version.clone(),
+            beta: (!beta.is_empty()).then_some(beta),
+            base_url: self.base_url.clone(),
+            middleware: self.middleware.clone(),
+            transport: self.shared_transport(),
+        }
     }
 }
 
+#[derive(Debug, Clone)]
+pub(crate) struct Connection {
+    client: ReqwestClient,
+    secret_key: SecretKey,
+    version: String,
+    beta: Option<String>,
+    base_url: String,
+    middleware: MiddlewareStack,
+    transport: SharedTransport,
+}
+
+impl Connection {
+    pub(crate) fn post(&self, path: &str) -> RequestBuilder {
+        let mut request_builder = self.client.post(format!("{}{}", self.base_url, path));
+        for (name, value) in self.headers() {
+            request_builder = match header_value(name, value) {
+                Ok(value) => request_builder.header(name, value),
+                // Leaves reqwest to report the invalid value when the request is built.
+                Err(_) => request_builder.header(name, value),
+            };
+        }
+        request_builder
+    }
+
+    /// Posts `body` to `path` through the middleware stack.
+    pub(crate) async fn send(&self, path: &str, body: &Value) -> Result<reqwest::Response> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.headers() {
+            headers.insert(
+                name,
+                header_value(name, value).with_context(|| format!("Invalid {name} header"))?,
+            );
+        }
+        let mut request = HttpRequest {
+            method: Method::POST,
+            url: Url::parse(&format!("{}{}", self.base_url, path)).context("Invalid base URL")?,
+            headers,
+            body: body.clone(),
+        };
+        self.middleware.before_send(&mut request).await?;
+
+        let request = self
+            .client
+            .request(request.method, request.url)
+            .headers(request.headers)
+            .json(&request.body)
+            .build()?;
+        let response = self.transport.0.send(request).await?;
+        self.middleware.after_receive(&response).await?;
+        Ok(response)
+    }
+
+    fn headers(&self) -> Vec<(&'static str, &str)> {
+        let mut headers = vec![
+            ("x-api-key", self.secret_key.expose()),
+            ("anthropic-version", self.version.as_str()),
+            ("content-type", "application/json"),
+        ];
+        if let Some(beta_value) = &self.beta {
+            headers.push(("anthropic-beta", beta_value));
+        }
+        headers
+    }
+}
+
+/// The API key is marked sensitive so it is left out of `Debug` output.
+fn header_value(name: &str, value: &str) -> Result<HeaderValue> {
+    let mut header = HeaderValue::from_str(value)?;
+    header.set_sensitive(name == "x-api-key");
+    Ok(header)
+}
+
 pub struct Request {
-    request_builder: RequestBuilder,
+    connection: Connection,
+    body: Value,
     stream: bool,
     verbose: bool,
-    tools: Value,
+    tools: Option<Value>,
+    context_policy: Option<ContextPolicy>,
+    max_retries: u32,
+    rate_limiter: Option<RateLimiter>,
+    cancellation: Option<CancellationToken>,
+    timeouts: Timeouts,
+    resume_attempts: u32,
+    fallback_models: Vec<String>,
+    usage_tracker: Option<UsageTracker>,
+    usage_tag: Option<String>,
+    telemetry: Telemetry,
+    on_stream_metrics: Option<MetricsHook>,
 }
 
 impl Request {
-    pub async fn execute<F, Fut>(self, mut callback: F) -> Result<()>
+    /// The JSON body that will be sent to the messages endpoint.
+    pub fn body(&self) -> &Value {
+        &self.body
+    }
+
+    /// The request as a copy-pasteable curl command. The API key is read from
+    /// `$ANTHROPIC_API_KEY` rather than included, and middleware is not applied.
+    pub fn to_curl(&self) -> String {
+        dry_run::curl(
+            &format!("{}{}", self.connection.base_url, MESSAGES_PATH),
+            &self.connection.headers(),
+            &self.body,
+        )
+    }
+
+    /// The URL, body and curl command of this request, without sending it.
+    pub fn prepare(&self) -> PreparedRequest {
+        PreparedRequest {
+            url: format!("{}{}", self.connection.base_url, MESSAGES_PATH),
+            body: self.body.clone(),
+            curl: self.to_curl(),
+        }
+    }
+
+    /// Counts the input tokens of this request with the count_tokens endpoint.
+    pub async fn count_tokens(&self) -> Result<u32> {
+        context::count_tokens(&self.connection, &self.body).await
+    }
+
+    async fn send(&mut self, span: &RequestSpan) -> Result<reqwest::Response> {
+        if let Some(policy) = &self.context_policy {
+            policy.apply(&self.connection, &mut self.body).await?;
+        }
+        if let Some(tracker) = &self.usage_tracker {
+            tracker.check(&self.body, self.usage_tag.as_deref())?;
+        }
+
+        let result = loop {
+            let result = self.send_with_retries(span).await;
+            let unavailable = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(error) => is_unavailable(error),
+            };
+            if !(unavailable && self.fall_back()) {
+                break result;
+            }
+        };
+
+        result.map_err(|error| match error.downcast_ref::<ReqwestError>() {
+            Some(reqwest_error) if reqwest_error.is_connect() && reqwest_error.is_timeout() => {
+                Timeout {
+                    phase: TimeoutPhase::Connect,
+                    after: self.timeouts.connect.unwrap_or_default(),
+                    partial: Completion::default(),
+                }
+                .into()
+            }
+            Some(_) => error.context("Failed to send request"),
+            None => error,
+        })
+    }
+
+    /// Records `usage` under the model the response reported, falling back to
+    /// the one that was requested.
+    fn record_usage(&self, model: Option<&str>, usage: &Usage) {
+        if let Some(tracker) = &self.usage_tracker {
+            let model = model.map_or_else(|| self.served_by(), str::to_owned);
+            tracker.record(&model, self.usage_tag.as_deref(), usage);
+        }
+    }
+
+    /// Records the usage of a stream that failed part way, if it got as far as
+    /// `message_start`.
+    fn record_partial_usage(&self, completion: &Completion) {
+        if completion.model.is_some() {
+            self.record_usage(completion.model.as_deref(), &completion.usage);
+        }
+    }
+
+    fn served_by(&self) -> String {
+        self.body["model"].as_str().unwrap_or_default().to_owned()
+    }
+
+    /// Switches the body to the next fallback model, if there is one.
+    fn fall_back(&mut self) -> bool {
+        match self.next_model() {
+            Some(model) => {
+                self.body["model"] = json!(model);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The fallback model after the one currently in the body, if any.
+    fn next_model(&self) -> Option<String> {
+        let current = self.body["model"].as_str().unwrap_or_default();
+        let next = match self
+            .fallback_models
+            .iter()
+            .position(|model| model == current)
+        {
+            Some(index) => index + 1,
+            None => 0,
+        };
+        self.fallback_models.get(next).cloned()
+    }
+
+    async fn send_with_retries(&self, span: &RequestSpan) -> Result<reqwest::Response> {
+        let mut attempt = 0;
+        loop {
+            if let Some(rate_limiter) = &self.rate_limiter {
+                let max_tokens = self.body["max_tokens"].as_u64().unwrap_or_default() as u32;
+                rate_limiter
+                    .acquire(context::estimate_input_tokens(&self.body), max_tokens)
+                    .await;
+            }
+
+            let pending = self.connection.send(MESSAGES_PATH, &self.body);
+            let result = match self.timeouts.first_byte {
+                Some(first_byte) => match tokio::time::timeout(first_byte, pending).await {
+                    Ok(result) => result,
+                    Err(_) => Err(Timeout {
+                        phase: TimeoutPhase::FirstByte,
+                        after: first_byte,
+                        partial: Completion::default(),
+                    }
+                    .into()),
+                },
+                None => pending.await,
+            };
+
+            let retry_after = match &result {
+                Ok(response) => {
+                    let rate_limit = RateLimitInfo::from_headers(response.headers());
+                    if let Some(rate_limiter) = &self.rate_limiter {
+                        rate_limiter.observe(&rate_limit);
+                    }
+                    if !is_retryable(response.status()) {
+                        return result;
+                    }
+                    rate_limit.retry_after
+                }
+                Err(error) if is_retryable_error(error) => None,
+                Err(_) => return result,
+            };
+
+            if attempt >= self.max_retries {
+                return result;
+            }
+            span.attempt(
+                attempt + 1,
+                &match &result {
+                    Ok(response) => response.status().to_string(),
+                    Err(error) => error.to_string(),
+                },
+            );
+            tokio::time::sleep(retry_after.unwrap_or_else(|| retry_delay(attempt))).await;
+            attempt += 1;
+        }
+    }
+
+    /// Sends the request, failing with an [`ApiError`] unless the answer is a 200.
+    async fn open(
+        &mut self,
+        span: &RequestSpan,
+        cancellation: &Option<CancellationToken>,
+        total: Option<Deadline>,
+        partial: &Completion,
+    ) -> Result<reqwest::Response> {
+        let response = guard(self.send(span), cancellation, total, partial).await?;
+        span.response(&response, &self.served_by());
+        if response.status() != StatusCode::OK {
+            return Err(status_error(response).await);
+        }
+        Ok(response)
+    }
+
+    /// Reads the body of `response`, sending the request again to the next
+    /// fallback model if it stalls before arriving.
+    async fn read_or_fall_back(
+        &mut self,
+        response: &mut reqwest::Response,
+        span: &RequestSpan,
+        cancellation: &Option<CancellationToken>,
+        total: Option<Deadline>,
+        partial: &Completion,
+    ) -> Result<String> {
+        loop {
+            match read_text(response, cancellation, self.timeouts, total, partial).await {
+                Err(error) if is_unavailable(&error) && self.fall_back() => {
+                    *response = self.open(span, cancellation, total, partial).await?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Aborts the request when `token` is cancelled. The connection is dropped
+    /// and `execute` returns a [`Cancelled`] error carrying the partial output.
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    pub async fn execute<F, Fut>(self, callback: F) -> Result<Response<Completion>>
     where
         F: FnMut(String) -> Fut,
         Fut: std::future::Future<Output = ()> + Send,
     {
-        let mut response = self
-            .request_builder
-            .send()
-            .await
-            .context("Failed to send request")?;
-
-        match response.status() {
-            StatusCode::OK => {
-                if self.stream {
-                    let mut buffer = String::new();
-                    while let Some(chunk) = response.chunk().await? {
-                        let s = match std::str::from_utf8(&chunk) {
-                            Ok(v) => v,
-                            Err(e) => panic!("Invalid UTF-8 sequence: {}", e),
+        let span = RequestSpan::new(&self.body, &self.telemetry);
+        let result = span.instrument(self.execute_traced(callback, &span)).await;
+        span.finish(result.as_ref().map(|response| response.body()));
+        result
+    }
+
+    async fn execute_traced<F, Fut>(
+        mut self,
+        mut callback: F,
+        span: &RequestSpan,
+    ) -> Result<Response<Completion>>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: std::future::Future<Output = ()> + Send,
+    {
+        let started = Instant::now();
+        let cancellation = self.cancellation.clone();
+        let timeouts = self.timeouts;
+        let total = Deadline::after(TimeoutPhase::Total, timeouts.total, started);
+        let mut completion = Completion::default();
+
+        let mut timer = StreamTimer::new(started);
+        let mut response = self.open(span, &cancellation, total, &completion).await?;
+        timer.first_byte();
+
+        if self.stream {
+            let mut original_body = self.body.clone();
+            let mut resumes = 0;
+            loop {
+                let mut parser = SseParser::default();
+                let mut idle = Deadline::after(TimeoutPhase::Idle, timeouts.idle, Instant::now());
+                let interrupted = 'read: loop {
+                    let chunk = match guard(
+                        async { response.chunk().await.map_err(anyhow::Error::from) },
+                        &cancellation,
+                        Deadline::earliest(idle, total),
+                        &completion,
+                    )
+                    .await
+                    {
+                        Ok(Some(chunk)) => chunk,
+                        Ok(None) => break None,
+                        Err(error) => break Some(error),
+                    };
+
+                    let events = match parser.push(&chunk) {
+                        Ok(events) => events,
+                        Err(error) => break Some(error),
+                    };
+                    for event in &events {
+                        let text = match completion.apply(event) {
+                            Ok(text) => text,
+                            Err(error) => break 'read Some(error),
                         };
-                        buffer.push_str(s);
-                        loop {
-                            if let Some(index) = buffer.find("\n\n") {
-                                let chunk = buffer[..index].to_string();
-                                buffer.drain(..=index + 1);
-
-                                if self.verbose {
-                                    callback(chunk.clone()).await;
-                                } else {
-                                    if chunk == "data: [DONE]" {
-                                        break;
-                                    }
-                                    let processed_chunk = chunk
-                                        .trim_start_matches("event: message_start")
-                                        .trim_start_matches("event: content_block_start")
-                                        .trim_start_matches("event: ping")
-                                        .trim_start_matches("event: content_block_delta")
-                                        .trim_start_matches("event: content_block_stop")
-                                        .trim_start_matches("event: message_delta")
-                                        .trim_start_matches("event: message_stop")
-                                        .to_string();
-                                    let cleaned_string = &processed_chunk
-                                        .trim_start()
-                                        .strip_prefix("data: ")
-                                        .unwrap_or(&processed_chunk);
-                                    match serde_json::from_str::<AnthropicChatCompletionChunk>(
-                                        &cleaned_string,
-                                    ) {
-                                        Ok(d) => {
-                                            if let Some(delta) = d.delta {
-                                                if let Some(content) = delta.text {
-                                                    callback(content).await;
-                                                }
-                                            }
-                                        }
-                                        Err(_) => {
-                                            let processed_chunk = cleaned_string
-                                                .trim_start_matches("event: error")
-                                                .to_string();
-                                            let cleaned_string = &processed_chunk
-                                                .trim_start()
-                                                .strip_prefix("data: ")
-                                                .unwrap_or(&processed_chunk);
-                                            match serde_json::from_str::<AnthropicErrorMessage>(
-                                                &cleaned_string,
-                                            ) {
-                                                Ok(error_message) => {
-                                                    return Err(anyhow!(
-                                                        "{}: {}",
-                                                        error_message.error.error_type,
-                                                        error_message.error.message
-                                                    ));
-                                                }
-                                                Err(_) => {
-                                                    eprintln!(
-                                                        "Couldn't parse AnthropicChatCompletionChunk or AnthropicErrorMessage: {}",
-                                                        &cleaned_string
-                                                    );
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            } else {
-                                break;
+                        if self.verbose {
+                            if text.is_some() {
+                                timer.text();
+                                span.text();
                             }
+                            callback(event.raw.clone()).await;
+                        } else if let Some(text) = text {
+                            timer.text();
+                            span.text();
+                            callback(text).await;
                         }
                     }
+                    if !events.is_empty() {
+                        idle = Deadline::after(TimeoutPhase::Idle, timeouts.idle, Instant::now());
+                    }
+                };
+
+                let Some(error) = interrupted else {
+                    break;
+                };
+                if completion.model.is_none() && is_unavailable(&error) && self.fall_back() {
+                    // Nothing arrived, so start over on the next model.
+                    original_body["model"] = self.body["model"].clone();
+                    self.body = original_body.clone();
+                } else if resumes >= self.resume_attempts || !is_resumable(&error) {
+                    self.record_partial_usage(&completion);
+                    return Err(error);
                 } else {
-                    let json_text = response
-                        .text()
-                        .await
-                        .context("Failed to read response text")?;
-                    if self.tools == Value::Null && !self.verbose {
-                        match serde_json::from_str::<JsonResponse>(&json_text) {
-                            Ok(parsed_json) => {
-                                if let Some(content) = parsed_json
-                                    .content
-                                    .iter()
-                                    .find(|c| c.content_type == "text")
-                                {
-                                    callback(content.text.clone()).await;
-                                }
-                            }
-                            Err(_) => return Err(anyhow!("Unable to parse JSON")),
-                        }
-                    } else {
-                        callback(json_text).await;
+                    // Continue from what we have by sending it back as an assistant prefill.
+                    resumes += 1;
+                    let prefill = completion.prepare_resume();
+                    self.body = with_prefill(&original_body, &prefill);
+                    // The continuation only gets what is left of the original max_tokens.
+                    if let Some(max_tokens) = original_body["max_tokens"].as_u64() {
+                        let used = u64::from(completion.usage.output_tokens);
+                        self.body["max_tokens"] = json!(max_tokens.saturating_sub(used).max(1));
                     }
                 }
-                Ok(())
+                response = match self.open(span, &cancellation, total, &completion).await {
+                    Ok(response) => response,
+                    Err(error) => {
+                        self.record_partial_usage(&completion);
+                        return Err(error);
+                    }
+                };
             }
-            StatusCode::BAD_REQUEST => Err(anyhow!(
-                "Bad request. Check your request parameters. {}",
-                response.text().await?
-            )),
-            StatusCode::UNAUTHORIZED => Err(anyhow!("Unauthorized. Check your authorization key.")),
-            StatusCode::TOO_MANY_REQUESTS => {
-                Err(anyhow!("Too many Requests. You have been rate limited."))
+
+            let metrics = timer.finish(completion.usage.output_tokens);
+            if let Some(MetricsHook(hook)) = &self.on_stream_metrics {
+                hook(&metrics);
             }
-            _ => {
-                let error_message = format!("Unexpected status code: {:?}", response.text().await?);
-                Err(anyhow!(error_message))
+            completion.metrics = Some(metrics);
+        } else {
+            let json_text = self
+                .read_or_fall_back(&mut response, span, &cancellation, total, &completion)
+                .await?;
+            if let Ok(anthropic_response) = serde_json::from_str::<AnthropicResponse>(&json_text) {
+                completion = Completion::from(&anthropic_response);
+            }
+            if self.tools.is_none() && !self.verbose {
+                match serde_json::from_str::<JsonResponse>(&json_text) {
+                    Ok(parsed_json) => {
+                        if let Some(text) = parsed_json.content.into_iter().find_map(|c| match c {
+                            Content::Text { text } => Some(text),
+                            Content::Other => None,
+                        }) {
+                            callback(text).await;
+                        }
+                    }
+                    Err(_) => return Err(anyhow!("Unable to parse JSON")),
+                }
+            } else {
+                callback(json_text).await;
             }
         }
+
+        self.record_usage(completion.model.as_deref(), &completion.usage);
+        let model = completion.model.clone();
+        Ok(Response::new(
+            completion,
+            model,
+            self.served_by(),
+            response.status(),
+            response.headers().clone(),
+            started,
+        ))
     }
-    pub async fn execute_and_return_json(self) -> Result<AnthropicResponse> {
-        let response = self
-            .request_builder
-            .send()
-            .await
-            .context("Failed to send request")?;
 
-        match response.status() {
-            StatusCode::OK => {
-                let json_text = response
-                    .text()
-                    .await
-                    .context("Failed to read response text")?;
+    pub async fn execute_and_return_json(self) -> Result<Response<AnthropicResponse>> {
+        let span = RequestSpan::new(&self.body, &self.telemetry);
+        let result = span.instrument(self.execute_json_traced(&span)).await;
+        match &result {
+            Ok(response) => span.finish(Ok(&Completion::from(response.body()))),
+            Err(error) => span.finish(Err(error)),
+        }
+        result
+    }
 
-                let anthropic_response: AnthropicResponse = serde_json::from_str(&json_text)
-                    .context("Failed to parse response as AnthropicResponse")?;
-                Ok(anthropic_response)
-            }
-            StatusCode::BAD_REQUEST => {
-                let error_text = response.text().await?;
-                Err(anyhow!(
-                    "Bad request. Check your request parameters. {}",
-                    error_text
-                ))
-            }
-            StatusCode::UNAUTHORIZED => Err(anyhow!("Unauthorized. Check your authorization key.")),
-            StatusCode::TOO_MANY_REQUESTS => {
-                Err(anyhow!("Too many Requests. You have been rate limited."))
-            }
-            _ => {
-                let error_message = format!("Unexpected status code: {}", response.status());
-                let error_text = response.text().await?;
-                Err(anyhow!("{}: {}", error_message, error_text))
-            }
+    async fn execute_json_traced(
+        mut self,
+        span: &RequestSpan,
+    ) -> Result<Response<AnthropicResponse>> {
+        let started = Instant::now();
+        let cancellation = self.cancellation.clone();
+        let timeouts = self.timeouts;
+        let total = Deadline::after(TimeoutPhase::Total, timeouts.total, started);
+        let partial = Completion::default();
+
+        let mut response = self.open(span, &cancellation, total, &partial).await?;
+        let json_text = self
+            .read_or_fall_back(&mut response, span, &cancellation, total, &partial)
+            .await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+
+        let anthropic_response: AnthropicResponse = serde_json::from_str(&json_text)
+            .context("Failed to parse response as AnthropicResponse")?;
+        self.record_usage(Some(&anthropic_response.model), &anthropic_response.usage);
+        let model = Some(anthropic_response.model.clone());
+        Ok(Response::new(
+            anthropic_response,
+            model,
+            self.served_by(),
+            status,
+            headers,
+            started,
+        ))
+    }
+}
+
+async fn status_error(response: reqwest::Response) -> anyhow::Error {
+    let status = response.status();
+    let headers = response.headers().clone();
+    match response.text().await {
+        Ok(error_text) => ApiError::new(status, &headers, &error_text).into(),
+        Err(error) => error.into(),
+    }
+}
+
+fn is_resumable(error: &anyhow::Error) -> bool {
+    if let Some(stream_error) = error.downcast_ref::<StreamError>() {
+        return matches!(
+            stream_error.error_type.as_str(),
+            "overloaded_error" | "api_error"
+        );
+    }
+    if let Some(timeout) = error.downcast_ref::<Timeout>() {
+        return timeout.phase == TimeoutPhase::Idle;
+    }
+    error.is::<ReqwestError>()
+}
+
+/// Reads a non-streamed body. The idle deadline restarts with every chunk, so a
+/// large body that is still arriving doesn't time out.
+async fn read_text(
+    response: &mut reqwest::Response,
+    cancellation: &Option<CancellationToken>,
+    timeouts: Timeouts,
+    total: Option<Deadline>,
+    partial: &Completion,
+) -> Result<String> {
+    let mut bytes = Vec::new();
+    loop {
+        let idle = Deadline::after(TimeoutPhase::Idle, timeouts.idle, Instant::now());
+        let chunk = guard(
+            async {
+                response
+                    .chunk()
+                    .await
+                    .context("Failed to read response text")
+            },
+            cancellation,
+            Deadline::earliest(idle, total),
+            partial,
+        )
+        .await?;
+        match chunk {
+            Some(chunk) => bytes.extend_from_slice(&chunk),
+            None => break,
         }
     }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Appends `prefill` to the conversation as assistant text, extending an existing
+/// trailing assistant message rather than adding a second one.
+fn with_prefill(body: &Value, prefill: &str) -> Value {
+    let mut body = body.clone();
+    if prefill.is_empty() {
+        return body;
+    }
+    let Some(messages) = body["messages"].as_array_mut() else {
+        return body;
+    };
+
+    match messages.last_mut() {
+        Some(last) if last["role"] == "assistant" => match &mut last["content"] {
+            Value::String(content) => content.push_str(prefill),
+            Value::Array(blocks) => match blocks.last_mut() {
+                Some(block) if block["type"] == "text" => {
+                    let text = format!("{}{}", block["text"].as_str().unwrap_or_default(), prefill);
+                    block["text"] = json!(text);
+                }
+                _ => blocks.push(json!({"type": "text", "text": prefill})),
+            },
+            content => *content = json!(prefill),
+        },
+        _ => messages.push(json!({"role": "assistant", "content": prefill})),
+    }
+    body
+}
+
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::REQUEST_TIMEOUT | StatusCode::CONFLICT | StatusCode::TOO_MANY_REQUESTS
+    ) || status.is_server_error()
+}
+
+/// Whether `error` means the model couldn't serve the request, so a fallback
+/// model should be tried.
+fn is_unavailable(error: &anyhow::Error) -> bool {
+    if let Some(api_error) = error.downcast_ref::<ApiError>() {
+        return api_error.status.is_server_error();
+    }
+    if let Some(timeout) = error.downcast_ref::<Timeout>() {
+        return timeout.phase != TimeoutPhase::Total;
+    }
+    if let Some(reqwest_error) = error.downcast_ref::<ReqwestError>() {
+        return reqwest_error.is_connect() || reqwest_error.is_timeout();
+    }
+    false
+}
+
+fn is_retryable_error(error: &anyhow::Error) -> bool {
+    if let Some(reqwest_error) = error.downcast_ref::<ReqwestError>() {
+        return reqwest_error.is_connect() || reqwest_error.is_timeout();
+    }
+    error.is::<Timeout>()
+}
+
+fn retry_delay(attempt: u32) -> Duration {
+    (Duration::from_millis(500) * 2u32.saturating_pow(attempt)).min(MAX_RETRY_DELAY)
 }
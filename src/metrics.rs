@@ -0,0 +1,101 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// Percentiles of the time between consecutive content deltas.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+/// Timings of a streamed response, measured from when `execute` was called.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamMetrics {
+    /// Until the response headers arrived.
+    pub time_to_first_byte: Duration,
+    /// Until the first text delta, if there was one.
+    pub time_to_first_token: Option<Duration>,
+    /// `None` with fewer than two text deltas.
+    pub inter_token_latency: Option<LatencyPercentiles>,
+    /// Output tokens per second after the first token.
+    pub output_tokens_per_second: Option<f64>,
+    pub text_deltas: usize,
+    pub total: Duration,
+}
+
+/// A callback that receives the metrics of every streamed request.
+#[derive(Clone)]
+pub(crate) struct MetricsHook(pub Arc<dyn Fn(&StreamMetrics) + Send + Sync>);
+
+impl fmt::Debug for MetricsHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MetricsHook")
+    }
+}
+
+pub(crate) struct StreamTimer {
+    started: Instant,
+    first_byte: Option<Duration>,
+    first_token: Option<Duration>,
+    last_token: Option<Instant>,
+    gaps: Vec<Duration>,
+}
+
+impl StreamTimer {
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            first_byte: None,
+            first_token: None,
+            last_token: None,
+            gaps: Vec::new(),
+        }
+    }
+
+    pub fn first_byte(&mut self) {
+        self.first_byte
+            .get_or_insert_with(|| self.started.elapsed());
+    }
+
+    pub fn text(&mut self) {
+        let now = Instant::now();
+        match self.last_token {
+            Some(last) => self.gaps.push(now - last),
+            None => self.first_token = Some(now - self.started),
+        }
+        self.last_token = Some(now);
+    }
+
+    pub fn finish(mut self, output_tokens: u32) -> StreamMetrics {
+        let total = self.started.elapsed();
+        let generating = self.first_token.map(|first_token| total - first_token);
+        self.gaps.sort();
+
+        StreamMetrics {
+            time_to_first_byte: self.first_byte.unwrap_or_default(),
+            time_to_first_token: self.first_token,
+            inter_token_latency: (!self.gaps.is_empty()).then(|| LatencyPercentiles {
+                p50: percentile(&self.gaps, 50),
+                p90: percentile(&self.gaps, 90),
+                p99: percentile(&self.gaps, 99),
+                max: self.gaps[self.gaps.len() - 1],
+            }),
+            output_tokens_per_second: generating
+                .filter(|generating| !generating.is_zero())
+                .map(|generating| f64::from(output_tokens) / generating.as_secs_f64()),
+            text_deltas: self.gaps.len() + usize::from(self.first_token.is_some()),
+            total,
+        }
+    }
+}
+
+/// Nearest-rank percentile of a sorted, non-empty slice.
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (sorted.len() * percent).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use reqwest::{Method, Url};
+use serde_json::Value;
+
+/// A request about to be sent, as [`Middleware`] sees it. The body is still JSON,
+/// so it can be rewritten field by field.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: Method,
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+/// Hooks run around every HTTP call a client makes, including count_tokens and
+/// the calls made by context strategies. `before_send` runs in the order the
+/// middleware were added and `after_receive` in reverse. An error from either
+/// fails the request without a retry.
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    async fn before_send(&self, _request: &mut HttpRequest) -> Result<()> {
+        Ok(())
+    }
+
+    async fn after_receive(&self, _response: &reqwest::Response) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct MiddlewareStack(Vec<Arc<dyn Middleware>>);
+
+impl fmt::Debug for MiddlewareStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "MiddlewareStack({})", self.0.len())
+    }
+}
+
+impl MiddlewareStack {
+    pub fn push(&mut self, middleware: Arc<dyn Middleware>) {
+        self.0.push(middleware);
+    }
+
+    pub async fn before_send(&self, request: &mut HttpRequest) -> Result<()> {
+        for middleware in &self.0 {
+            middleware.before_send(request).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn after_receive(&self, response: &reqwest::Response) -> Result<()> {
+        for middleware in self.0.iter().rev() {
+            middleware.after_receive(response).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,715 @@
+//! A local stand-in for the Anthropic API for end-to-end tests, behind the
+//! `mock-server` feature. It serves `/v1/messages` (JSON and streamed),
+//! `/v1/messages/count_tokens`, `/v1/models` and message batches, answering
+//! messages from a script of [`ScriptedReply`]s.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use axum::body::{Body, Bytes};
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use futures::stream::{self, StreamExt};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+use crate::{HttpRequest, Model};
+
+const CREATED_AT: &str = "2025-01-01T00:00:00Z";
+
+/// How the server answers one message request. Replies are used in the order
+/// they were scripted; once they run out, the default reply is used.
+///
+/// Replies are also accepted as JSON, by the `--script` option of the
+/// `anthropic-mock-server` binary and by `POST /mock/replies`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptedReply {
+    #[serde(default)]
+    content: Vec<Value>,
+    #[serde(default = "end_turn")]
+    stop_reason: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<InjectedError>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stream_error: Option<InjectedError>,
+    /// How many content deltas are sent before `stream_error`.
+    #[serde(default)]
+    stream_error_after: usize,
+    #[serde(default)]
+    latency_ms: u64,
+    #[serde(default)]
+    chunk_delay_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chunk_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct InjectedError {
+    #[serde(default = "server_error")]
+    status: u16,
+    #[serde(rename = "type")]
+    error_type: String,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+}
+
+fn end_turn() -> String {
+    "end_turn".to_owned()
+}
+
+fn server_error() -> u16 {
+    500
+}
+
+impl ScriptedReply {
+    pub fn text(text: &str) -> Self {
+        Self {
+            content: vec![json!({"type": "text", "text": text})],
+            stop_reason: end_turn(),
+            error: None,
+            stream_error: None,
+            stream_error_after: 0,
+            latency_ms: 0,
+            chunk_delay_ms: 0,
+            chunk_size: None,
+        }
+    }
+
+    /// A call to the tool `name`, ending with `stop_reason: "tool_use"`.
+    pub fn tool_use(name: &str, input: Value) -> Self {
+        Self {
+            content: vec![json!({
+                "type": "tool_use",
+                "id": "toolu_mock",
+                "name": name,
+                "input": input,
+            })],
+            stop_reason: "tool_use".to_owned(),
+            ..Self::text("")
+        }
+    }
+
+    /// An error response, such as `ScriptedReply::error(500, "api_error", "Internal error")`.
+    pub fn error(status: u16, error_type: &str, message: &str) -> Self {
+        Self {
+            content: Vec::new(),
+            error: Some(InjectedError {
+                status,
+                error_type: error_type.to_owned(),
+                message: message.to_owned(),
+                retry_after: None,
+            }),
+            ..Self::text("")
+        }
+    }
+
+    /// A 429 with a `retry-after` header.
+    pub fn rate_limited(retry_after_secs: u64) -> Self {
+        Self::error(429, "rate_limit_error", "Rate limited").retry_after(retry_after_secs)
+    }
+
+    /// A 529 `overloaded_error`.
+    pub fn overloaded() -> Self {
+        Self::error(529, "overloaded_error", "Overloaded")
+    }
+
+    /// Sets the `retry-after` header of an error reply.
+    pub fn retry_after(mut self, secs: u64) -> Self {
+        if let Some(error) = &mut self.error {
+            error.retry_after = Some(secs);
+        }
+        self
+    }
+
+    /// Ends a streamed reply with an `error` event after `deltas` content
+    /// deltas. JSON replies are unaffected.
+    pub fn fail_stream_after(mut self, deltas: usize, error_type: &str, message: &str) -> Self {
+        self.stream_error = Some(InjectedError {
+            status: server_error(),
+            error_type: error_type.to_owned(),
+            message: message.to_owned(),
+            retry_after: None,
+        });
+        self.stream_error_after = deltas;
+        self
+    }
+
+    /// Waits `latency` before sending the response headers.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency_ms = latency.as_millis() as u64;
+        self
+    }
+
+    /// Waits `delay` between the chunks of a streamed body.
+    pub fn chunk_delay(mut self, delay: Duration) -> Self {
+        self.chunk_delay_ms = delay.as_millis() as u64;
+        self
+    }
+
+    /// Splits a streamed body into chunks of `size` bytes, regardless of where
+    /// events end. By default each event is sent as one chunk.
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = Some(size.max(1));
+        self
+    }
+}
+
+struct ServerState {
+    url: String,
+    replies: VecDeque<ScriptedReply>,
+    default_reply: ScriptedReply,
+    requests: Vec<HttpRequest>,
+    batches: Vec<Batch>,
+    next_id: u64,
+}
+
+struct Batch {
+    body: Value,
+    results: Vec<Value>,
+}
+
+type Shared = Arc<Mutex<ServerState>>;
+
+/// A mock Anthropic API listening on a local port until it is dropped.
+///
+/// ```no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// use anthropic_sdk::{Client, MockServer, ScriptedReply};
+///
+/// let server = MockServer::start().await?;
+/// server.reply(ScriptedReply::overloaded());
+/// server.reply(ScriptedReply::text("Hello"));
+///
+/// let client = Client::new().auth("test").base_url(server.url());
+/// # Ok(())
+/// # }
+/// ```
+pub struct MockServer {
+    url: String,
+    state: Shared,
+    task: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for MockServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockServer")
+            .field("url", &self.url)
+            .finish()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl MockServer {
+    /// Listens on a free port on 127.0.0.1.
+    pub async fn start() -> Result<Self> {
+        Self::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let state = Arc::new(Mutex::new(ServerState {
+            url: url.clone(),
+            replies: VecDeque::new(),
+            default_reply: ScriptedReply::text("Hello from the mock server."),
+            requests: Vec::new(),
+            batches: Vec::new(),
+            next_id: 0,
+        }));
+
+        let app = Router::new()
+            .route("/v1/messages", post(messages))
+            .route("/v1/messages/count_tokens", post(count_tokens))
+            .route("/v1/messages/batches", post(create_batch).get(list_batches))
+            .route(
+                "/v1/messages/batches/:id",
+                get(get_batch).delete(delete_batch),
+            )
+            .route("/v1/messages/batches/:id/cancel", post(get_batch))
+            .route("/v1/messages/batches/:id/results", get(batch_results))
+            .route("/v1/models", get(list_models))
+            .route("/v1/models/:id", get(get_model))
+            .route("/mock/replies", post(script_replies))
+            .with_state(state.clone());
+        let task = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Ok(Self { url, state, task })
+    }
+
+    /// The base URL to pass to [`Client::base_url`](crate::Client::base_url).
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Queues `reply` as the answer to the next unanswered message request.
+    pub fn reply(&self, reply: ScriptedReply) {
+        self.state.lock().unwrap().replies.push_back(reply);
+    }
+
+    /// Answers message requests with `reply` once the script runs out.
+    pub fn default_reply(&self, reply: ScriptedReply) {
+        self.state.lock().unwrap().default_reply = reply;
+    }
+
+    /// Every request received so far, with API keys as they were sent.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl ServerState {
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{prefix}_mock_{}", self.next_id)
+    }
+
+    fn next_reply(&mut self) -> ScriptedReply {
+        self.replies
+            .pop_front()
+            .unwrap_or_else(|| self.default_reply.clone())
+    }
+}
+
+/// Records a request to the API and checks it has a key and, for a POST, a
+/// JSON body.
+fn receive(
+    state: &Shared,
+    method: Method,
+    uri: &Uri,
+    headers: HeaderMap,
+    body: &[u8],
+) -> Result<Value, InjectedError> {
+    let mut state = state.lock().unwrap();
+    let body = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(body).map_err(|error| InjectedError {
+            status: 400,
+            error_type: "invalid_request_error".to_owned(),
+            message: format!("Request body is not valid JSON: {error}"),
+            retry_after: None,
+        })?
+    };
+    let authorized = headers.contains_key("x-api-key") || headers.contains_key("authorization");
+    if let Ok(url) = Url::parse(&format!("{}{uri}", state.url)) {
+        state.requests.push(HttpRequest {
+            method,
+            url,
+            headers,
+            body: body.clone(),
+        });
+    }
+
+    if !authorized {
+        return Err(InjectedError {
+            status: 401,
+            error_type: "authentication_error".to_owned(),
+            message: "x-api-key header is required".to_owned(),
+            retry_after: None,
+        });
+    }
+    Ok(body)
+}
+
+fn error_response(error: &InjectedError) -> Response {
+    let status = StatusCode::from_u16(error.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut response = (status, Json(error_body(error))).into_response();
+    if let Some(retry_after) = error.retry_after {
+        response
+            .headers_mut()
+            .insert("retry-after", retry_after.into());
+    }
+    response
+}
+
+fn error_body(error: &InjectedError) -> Value {
+    json!({"type": "error", "error": {"type": error.error_type, "message": error.message}})
+}
+
+fn not_found(message: String) -> Response {
+    error_response(&InjectedError {
+        status: 404,
+        error_type: "not_found_error".to_owned(),
+        message,
+        retry_after: None,
+    })
+}
+
+/// A rough token count: one token per four bytes of JSON.
+fn estimate_tokens(value: &Value) -> u32 {
+    if value.is_null() {
+        return 0;
+    }
+    (value.to_string().len() as u32).div_ceil(4)
+}
+
+fn input_tokens(request: &Value) -> u32 {
+    estimate_tokens(&request["system"])
+        + estimate_tokens(&request["messages"])
+        + estimate_tokens(&request["tools"])
+}
+
+fn message(id: String, request: &Value, reply: &ScriptedReply) -> Value {
+    json!({
+        "id": id,
+        "type": "message",
+        "role": "assistant",
+        "model": request["model"],
+        "content": reply.content,
+        "stop_reason": reply.stop_reason,
+        "stop_sequence": null,
+        "usage": {
+            "input_tokens": input_tokens(request),
+            "output_tokens": estimate_tokens(&Value::from(reply.content.clone())),
+        },
+    })
+}
+
+async fn messages(
+    State(state): State<Shared>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = match receive(&state, Method::POST, &uri, headers, &body) {
+        Ok(request) => request,
+        Err(error) => return error_response(&error),
+    };
+    let (reply, id, request_id) = {
+        let mut state = state.lock().unwrap();
+        (
+            state.next_reply(),
+            state.next_id("msg"),
+            state.next_id("req"),
+        )
+    };
+
+    tokio::time::sleep(Duration::from_millis(reply.latency_ms)).await;
+    let mut response = if let Some(error) = &reply.error {
+        error_response(error)
+    } else if request["stream"] == true {
+        stream_response(id, &request, &reply)
+    } else {
+        Json(message(id, &request, &reply)).into_response()
+    };
+    response
+        .headers_mut()
+        .insert("request-id", request_id.parse().unwrap());
+    response
+}
+
+fn stream_response(id: String, request: &Value, reply: &ScriptedReply) -> Response {
+    let mut start = message(id, request, reply);
+    start["content"] = json!([]);
+    start["stop_reason"] = Value::Null;
+    start["usage"]["output_tokens"] = json!(1);
+    let output_tokens = estimate_tokens(&Value::from(reply.content.clone()));
+
+    let mut events = vec![json!({"type": "message_start", "message": start})];
+    let mut deltas = 0;
+    'blocks: for (index, block) in reply.content.iter().enumerate() {
+        let pieces: Vec<Value> = match block["type"].as_str() {
+            Some("text") => {
+                let mut start = block.clone();
+                start["text"] = json!("");
+                events.push(
+                    json!({"type": "content_block_start", "index": index, "content_block": start}),
+                );
+                block["text"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .split_inclusive(' ')
+                    .map(|text| json!({"type": "text_delta", "text": text}))
+                    .collect()
+            }
+            Some("tool_use") => {
+                let mut start = block.clone();
+                start["input"] = json!({});
+                events.push(
+                    json!({"type": "content_block_start", "index": index, "content_block": start}),
+                );
+                let input: Vec<char> = block["input"].to_string().chars().collect();
+                input
+                    .chunks(16)
+                    .map(|json| {
+                        json!({"type": "input_json_delta", "partial_json": String::from_iter(json)})
+                    })
+                    .collect()
+            }
+            _ => {
+                events.push(
+                    json!({"type": "content_block_start", "index": index, "content_block": block}),
+                );
+                Vec::new()
+            }
+        };
+
+        for delta in pieces {
+            if reply.stream_error.is_some() && deltas == reply.stream_error_after {
+                break 'blocks;
+            }
+            events.push(json!({"type": "content_block_delta", "index": index, "delta": delta}));
+            deltas += 1;
+        }
+        events.push(json!({"type": "content_block_stop", "index": index}));
+    }
+
+    match &reply.stream_error {
+        Some(error) => events.push(error_body(error)),
+        None => events.extend([
+            json!({
+                "type": "message_delta",
+                "delta": {"stop_reason": reply.stop_reason, "stop_sequence": null},
+                "usage": {"output_tokens": output_tokens},
+            }),
+            json!({"type": "message_stop"}),
+        ]),
+    }
+
+    let events: Vec<Vec<u8>> = events
+        .iter()
+        .map(|event| {
+            format!(
+                "event: {}\ndata: {event}\n\n",
+                event["type"].as_str().unwrap()
+            )
+            .into_bytes()
+        })
+        .collect();
+    let chunks = match reply.chunk_size {
+        Some(size) => events.concat().chunks(size).map(<[u8]>::to_vec).collect(),
+        None => events,
+    };
+    let delay = Duration::from_millis(reply.chunk_delay_ms);
+    let body =
+        stream::iter(chunks.into_iter().enumerate()).then(move |(index, chunk)| async move {
+            if index > 0 {
+                tokio::time::sleep(delay).await;
+            }
+            Ok::<_, std::io::Error>(chunk)
+        });
+
+    (
+        [("content-type", "text/event-stream")],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+async fn count_tokens(
+    State(state): State<Shared>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    match receive(&state, Method::POST, &uri, headers, &body) {
+        Ok(request) => Json(json!({"input_tokens": input_tokens(&request)})).into_response(),
+        Err(error) => error_response(&error),
+    }
+}
+
+fn model_json(model: &Model) -> Value {
+    json!({"type": "model", "id": model.id, "display_name": model.id, "created_at": CREATED_AT})
+}
+
+async fn list_models(State(state): State<Shared>, uri: Uri, headers: HeaderMap) -> Response {
+    if let Err(error) = receive(&state, Method::GET, &uri, headers, &[]) {
+        return error_response(&error);
+    }
+    let models: Vec<Value> = Model::all().iter().map(model_json).collect();
+    Json(json!({
+        "data": models,
+        "has_more": false,
+        "first_id": models.first().map(|model| model["id"].clone()),
+        "last_id": models.last().map(|model| model["id"].clone()),
+    }))
+    .into_response()
+}
+
+async fn get_model(
+    State(state): State<Shared>,
+    Path(id): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(error) = receive(&state, Method::GET, &uri, headers, &[]) {
+        return error_response(&error);
+    }
+    match Model::lookup(&id) {
+        Some(model) => Json(model_json(&model)).into_response(),
+        None => not_found(format!("model: {id}")),
+    }
+}
+
+async fn create_batch(
+    State(state): State<Shared>,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let request = match receive(&state, Method::POST, &uri, headers, &body) {
+        Ok(request) => request,
+        Err(error) => return error_response(&error),
+    };
+
+    let mut state = state.lock().unwrap();
+    let id = state.next_id("msgbatch");
+    let mut results = Vec::new();
+    let (mut succeeded, mut errored) = (0, 0);
+    for entry in request["requests"].as_array().into_iter().flatten() {
+        let reply = state.next_reply();
+        let result = match &reply.error {
+            Some(error) => {
+                errored += 1;
+                json!({"type": "errored", "error": error_body(error)})
+            }
+            None => {
+                succeeded += 1;
+                let message_id = state.next_id("msg");
+                json!({"type": "succeeded", "message": message(message_id, &entry["params"], &reply)})
+            }
+        };
+        results.push(json!({"custom_id": entry["custom_id"], "result": result}));
+    }
+
+    let body = json!({
+        "id": id,
+        "type": "message_batch",
+        "processing_status": "ended",
+        "request_counts": {
+            "processing": 0,
+            "succeeded": succeeded,
+            "errored": errored,
+            "canceled": 0,
+            "expired": 0,
+        },
+        "created_at": CREATED_AT,
+        "ended_at": CREATED_AT,
+        "expires_at": CREATED_AT,
+        "cancel_initiated_at": null,
+        "archived_at": null,
+        "results_url": format!("{}/v1/messages/batches/{id}/results", state.url),
+    });
+    state.batches.push(Batch {
+        body: body.clone(),
+        results,
+    });
+    Json(body).into_response()
+}
+
+async fn list_batches(State(state): State<Shared>, uri: Uri, headers: HeaderMap) -> Response {
+    if let Err(error) = receive(&state, Method::GET, &uri, headers, &[]) {
+        return error_response(&error);
+    }
+    let state = state.lock().unwrap();
+    let batches: Vec<Value> = state
+        .batches
+        .iter()
+        .rev()
+        .map(|batch| batch.body.clone())
+        .collect();
+    Json(json!({
+        "data": batches,
+        "has_more": false,
+        "first_id": batches.first().map(|batch| batch["id"].clone()),
+        "last_id": batches.last().map(|batch| batch["id"].clone()),
+    }))
+    .into_response()
+}
+
+/// Also answers cancellation, since every batch has already ended.
+async fn get_batch(
+    State(state): State<Shared>,
+    Path(id): Path<String>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(error) = receive(&state, method, &uri, headers, &[]) {
+        return error_response(&error);
+    }
+    let state = state.lock().unwrap();
+    match state.batches.iter().find(|batch| batch.body["id"] == id) {
+        Some(batch) => Json(batch.body.clone()).into_response(),
+        None => not_found(format!("message_batch: {id}")),
+    }
+}
+
+async fn delete_batch(
+    State(state): State<Shared>,
+    Path(id): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(error) = receive(&state, Method::DELETE, &uri, headers, &[]) {
+        return error_response(&error);
+    }
+    let mut state = state.lock().unwrap();
+    let before = state.batches.len();
+    state.batches.retain(|batch| batch.body["id"] != id);
+    if state.batches.len() == before {
+        return not_found(format!("message_batch: {id}"));
+    }
+    Json(json!({"id": id, "type": "message_batch_deleted"})).into_response()
+}
+
+async fn batch_results(
+    State(state): State<Shared>,
+    Path(id): Path<String>,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Response {
+    if let Err(error) = receive(&state, Method::GET, &uri, headers, &[]) {
+        return error_response(&error);
+    }
+    let state = state.lock().unwrap();
+    let Some(batch) = state.batches.iter().find(|batch| batch.body["id"] == id) else {
+        return not_found(format!("message_batch: {id}"));
+    };
+    let lines: String = batch
+        .results
+        .iter()
+        .map(|result| format!("{result}\n"))
+        .collect();
+    ([("content-type", "application/x-jsonl")], lines).into_response()
+}
+
+/// Appends one reply, or an array of them, to the script.
+async fn script_replies(State(state): State<Shared>, body: Bytes) -> Response {
+    let replies = match serde_json::from_slice::<Value>(&body) {
+        Ok(Value::Array(replies)) => {
+            serde_json::from_value::<Vec<ScriptedReply>>(Value::Array(replies))
+        }
+        Ok(reply) => serde_json::from_value::<ScriptedReply>(reply).map(|reply| vec![reply]),
+        Err(error) => Err(error),
+    };
+    match replies {
+        Ok(replies) => {
+            state.lock().unwrap().replies.extend(replies);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Err(error) => error_response(&InjectedError {
+            status: 400,
+            error_type: "invalid_request_error".to_owned(),
+            message: error.to_string(),
+            retry_after: None,
+        }),
+    }
+}
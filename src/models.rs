@@ -0,0 +1,191 @@
+use std::sync::{OnceLock, RwLock};
+
+use serde::{Deserialize, Serialize};
+
+/// What the crate knows about a model. The built-in catalog can be extended or
+/// corrected at runtime with [`Model::register`], so a new model doesn't have to
+/// wait for a release.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Model {
+    /// The dated snapshot ID, such as `claude-sonnet-4-5-20250929`.
+    pub id: String,
+    /// Other names the API accepts for this snapshot, such as `claude-sonnet-4-5`.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub context_window: u32,
+    pub max_output_tokens: u32,
+    #[serde(default)]
+    pub vision: bool,
+    #[serde(default)]
+    pub pdf: bool,
+    #[serde(default)]
+    pub extended_thinking: bool,
+    /// Whether the model rejects `top_k` and `top_p` in the same request.
+    #[serde(default)]
+    pub exclusive_sampling: bool,
+    /// The shortest prompt prefix that can be cached.
+    pub min_cacheable_tokens: u32,
+    /// `YYYY-MM-DD` dates announced for deprecation and retirement.
+    #[serde(default)]
+    pub deprecated: Option<String>,
+    #[serde(default)]
+    pub retired: Option<String>,
+}
+
+impl Model {
+    /// Finds a model by snapshot ID or alias. A dated snapshot missing from the
+    /// catalog resolves to the model its undated name is an alias of.
+    pub fn lookup(name: &str) -> Option<Model> {
+        let catalog = catalog().read().unwrap();
+        let find = |name: &str| {
+            catalog
+                .iter()
+                .rev()
+                .find(|model| model.id == name || model.aliases.iter().any(|alias| alias == name))
+                .cloned()
+        };
+
+        find(name).or_else(|| {
+            let (base, date) = name.rsplit_once('-')?;
+            let dated = date.len() == 8 && date.bytes().all(|b| b.is_ascii_digit());
+            dated.then(|| find(base)).flatten()
+        })
+    }
+
+    /// Adds `model` to the catalog, replacing any entry with the same ID.
+    pub fn register(model: Model) {
+        let mut catalog = catalog().write().unwrap();
+        catalog.retain(|existing| existing.id != model.id);
+        catalog.push(model);
+    }
+
+    /// Every model in the catalog, built-in and registered.
+    pub fn all() -> Vec<Model> {
+        catalog().read().unwrap().clone()
+    }
+}
+
+fn catalog() -> &'static RwLock<Vec<Model>> {
+    static CATALOG: OnceLock<RwLock<Vec<Model>>> = OnceLock::new();
+    CATALOG.get_or_init(|| RwLock::new(builtin()))
+}
+
+fn model(id: &str, aliases: &[&str], max_output_tokens: u32, min_cacheable_tokens: u32) -> Model {
+    Model {
+        id: id.to_owned(),
+        aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+        context_window: 200_000,
+        max_output_tokens,
+        vision: true,
+        pdf: true,
+        extended_thinking: false,
+        exclusive_sampling: false,
+        min_cacheable_tokens,
+        deprecated: None,
+        retired: None,
+    }
+}
+
+fn builtin() -> Vec<Model> {
+    vec![
+        Model {
+            pdf: false,
+            ..model("claude-3-haiku-20240307", &[], 4096, 2048)
+        },
+        Model {
+            pdf: false,
+            deprecated: Some("2025-06-30".to_owned()),
+            retired: Some("2026-01-05".to_owned()),
+            ..model(
+                "claude-3-opus-20240229",
+                &["claude-3-opus-latest"],
+                4096,
+                1024,
+            )
+        },
+        Model {
+            pdf: false,
+            deprecated: Some("2025-08-13".to_owned()),
+            retired: Some("2025-10-22".to_owned()),
+            ..model("claude-3-5-sonnet-20240620", &[], 8192, 1024)
+        },
+        Model {
+            deprecated: Some("2025-08-13".to_owned()),
+            retired: Some("2025-10-22".to_owned()),
+            ..model(
+                "claude-3-5-sonnet-20241022",
+                &["claude-3-5-sonnet-latest"],
+                8192,
+                1024,
+            )
+        },
+        model(
+            "claude-3-5-haiku-20241022",
+            &["claude-3-5-haiku-latest"],
+            8192,
+            2048,
+        ),
+        Model {
+            extended_thinking: true,
+            ..model(
+                "claude-3-7-sonnet-20250219",
+                &["claude-3-7-sonnet-latest"],
+                64_000,
+                1024,
+            )
+        },
+        Model {
+            extended_thinking: true,
+            ..model(
+                "claude-sonnet-4-20250514",
+                &["claude-sonnet-4-0"],
+                64_000,
+                1024,
+            )
+        },
+        Model {
+            extended_thinking: true,
+            ..model("claude-opus-4-20250514", &["claude-opus-4-0"], 32_000, 1024)
+        },
+        Model {
+            extended_thinking: true,
+            exclusive_sampling: true,
+            ..model(
+                "claude-opus-4-1-20250805",
+                &["claude-opus-4-1"],
+                32_000,
+                1024,
+            )
+        },
+        Model {
+            extended_thinking: true,
+            exclusive_sampling: true,
+            ..model(
+                "claude-sonnet-4-5-20250929",
+                &["claude-sonnet-4-5"],
+                64_000,
+                1024,
+            )
+        },
+        Model {
+            extended_thinking: true,
+            exclusive_sampling: true,
+            ..model(
+                "claude-haiku-4-5-20251001",
+                &["claude-haiku-4-5"],
+                64_000,
+                4096,
+            )
+        },
+        Model {
+            extended_thinking: true,
+            exclusive_sampling: true,
+            ..model(
+                "claude-opus-4-5-20251101",
+                &["claude-opus-4-5"],
+                64_000,
+                4096,
+            )
+        },
+    ]
+}
@@ -0,0 +1,172 @@
+use ::prometheus::core::Collector;
+use ::prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use anyhow::Result;
+
+use crate::{Completion, RateLimitInfo};
+
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+const FIRST_TOKEN_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0];
+
+/// Counters and histograms for every request sent by the clients this handle is
+/// passed to, rendered in the Prometheus text format. Needs the `prometheus`
+/// feature. Clones record into the same metrics.
+#[derive(Debug, Clone)]
+pub struct PrometheusMetrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    retries: IntCounterVec,
+    duration: HistogramVec,
+    time_to_first_token: HistogramVec,
+    tokens: IntCounterVec,
+    rate_limit_remaining: IntGaugeVec,
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PrometheusMetrics {
+    /// Records into a registry of its own.
+    pub fn new() -> Self {
+        Self::with_registry(Registry::new()).expect("Failed to register metrics")
+    }
+
+    /// Records into `registry`, e.g. the one the rest of a service exports.
+    pub fn with_registry(registry: Registry) -> Result<Self> {
+        let metrics = Self {
+            requests: IntCounterVec::new(
+                Opts::new("anthropic_requests_total", "Requests by outcome"),
+                &["model", "status", "error_type"],
+            )?,
+            retries: IntCounterVec::new(
+                Opts::new("anthropic_retries_total", "Retried attempts"),
+                &["model"],
+            )?,
+            duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "anthropic_request_duration_seconds",
+                    "Time from sending a request until its response was read",
+                )
+                .buckets(LATENCY_BUCKETS.to_vec()),
+                &["model"],
+            )?,
+            time_to_first_token: HistogramVec::new(
+                HistogramOpts::new(
+                    "anthropic_time_to_first_token_seconds",
+                    "Time until the first text delta of a streamed response",
+                )
+                .buckets(FIRST_TOKEN_BUCKETS.to_vec()),
+                &["model"],
+            )?,
+            tokens: IntCounterVec::new(
+                Opts::new("anthropic_tokens_total", "Tokens by type"),
+                &["model", "type"],
+            )?,
+            rate_limit_remaining: IntGaugeVec::new(
+                Opts::new(
+                    "anthropic_rate_limit_remaining",
+                    "Remaining rate limit reported by the last response",
+                ),
+                &["resource"],
+            )?,
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 6] = [
+            Box::new(metrics.requests.clone()),
+            Box::new(metrics.retries.clone()),
+            Box::new(metrics.duration.clone()),
+            Box::new(metrics.time_to_first_token.clone()),
+            Box::new(metrics.tokens.clone()),
+            Box::new(metrics.rate_limit_remaining.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+        Ok(metrics)
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Every metric in the registry in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Failed to encode metrics");
+        String::from_utf8(buffer).expect("Metrics are not valid UTF-8")
+    }
+
+    pub(crate) fn retry(&self, model: &str) {
+        self.retries.with_label_values(&[model]).inc();
+    }
+
+    pub(crate) fn rate_limit(&self, rate_limit: &RateLimitInfo) {
+        let resources = [
+            ("requests", &rate_limit.requests),
+            ("tokens", &rate_limit.tokens),
+            ("input_tokens", &rate_limit.input_tokens),
+            ("output_tokens", &rate_limit.output_tokens),
+        ];
+        for (resource, limit) in resources {
+            if let Some(remaining) = limit.remaining {
+                self.rate_limit_remaining
+                    .with_label_values(&[resource])
+                    .set(i64::try_from(remaining).unwrap_or(i64::MAX));
+            }
+        }
+    }
+
+    pub(crate) fn finish(
+        &self,
+        model: &str,
+        status: &str,
+        error_type: &str,
+        seconds: f64,
+        completion: Option<&Completion>,
+    ) {
+        self.requests
+            .with_label_values(&[model, status, error_type])
+            .inc();
+        self.duration.with_label_values(&[model]).observe(seconds);
+
+        let Some(completion) = completion else {
+            return;
+        };
+        if let Some(first_token) = completion
+            .metrics
+            .and_then(|metrics| metrics.time_to_first_token)
+        {
+            self.time_to_first_token
+                .with_label_values(&[model])
+                .observe(first_token.as_secs_f64());
+        }
+
+        let usage = &completion.usage;
+        let tokens = [
+            ("input", usage.input_tokens),
+            ("output", usage.output_tokens),
+            (
+                "cache_read",
+                usage.cache_read_input_tokens.unwrap_or_default(),
+            ),
+            (
+                "cache_creation",
+                usage.cache_creation_input_tokens.unwrap_or_default(),
+            ),
+        ];
+        for (kind, count) in tokens {
+            self.tokens
+                .with_label_values(&[model, kind])
+                .inc_by(u64::from(count));
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::response::{RateLimit, RateLimitInfo};
+
+/// Per-minute limits to seed a [`RateLimiter`] with, usually those of your usage tier.
+/// Limits left as `None` are learned from the rate-limit response headers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    pub requests_per_minute: Option<u32>,
+    pub input_tokens_per_minute: Option<u32>,
+    pub output_tokens_per_minute: Option<u32>,
+}
+
+impl RateLimits {
+    pub fn new(
+        requests_per_minute: u32,
+        input_tokens_per_minute: u32,
+        output_tokens_per_minute: u32,
+    ) -> Self {
+        Self {
+            requests_per_minute: Some(requests_per_minute),
+            input_tokens_per_minute: Some(input_tokens_per_minute),
+            output_tokens_per_minute: Some(output_tokens_per_minute),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(per_minute: u32, now: Instant) -> Self {
+        Self {
+            capacity: per_minute as f64,
+            available: per_minute as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + self.capacity / 60.0 * elapsed).min(self.capacity);
+        self.updated = now;
+    }
+
+    fn wait(&self, amount: f64) -> Duration {
+        // A single request larger than the whole bucket only waits for a full one.
+        let amount = amount.min(self.capacity);
+        if self.available >= amount || self.capacity <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((amount - self.available) / (self.capacity / 60.0))
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.available -= amount.min(self.capacity);
+    }
+}
+
+fn observe_bucket(bucket: &mut Option<Bucket>, limit: &RateLimit, now: Instant) {
+    if let Some(capacity) = limit.limit {
+        let bucket = bucket.get_or_insert_with(|| Bucket::new(capacity as u32, now));
+        bucket.refill(now);
+        bucket.capacity = capacity as f64;
+    }
+    if let (Some(bucket), Some(remaining)) = (bucket.as_mut(), limit.remaining) {
+        // The server sees every client sharing the key, so its count wins.
+        bucket.available = (remaining as f64).min(bucket.capacity);
+        bucket.updated = now;
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    requests: Option<Bucket>,
+    input_tokens: Option<Bucket>,
+    output_tokens: Option<Bucket>,
+    blocked_until: Option<Instant>,
+}
+
+/// A client-side limiter with token buckets for requests, input tokens and output
+/// tokens per minute. Clones share the same buckets, so one limiter can be handed
+/// to every `Client` using the same organization key.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    state: Arc<Mutex<State>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        let now = Instant::now();
+        Self {
+            state: Arc::new(Mutex::new(State {
+                requests: limits.requests_per_minute.map(|l| Bucket::new(l, now)),
+                input_tokens: limits.input_tokens_per_minute.map(|l| Bucket::new(l, now)),
+                output_tokens: limits.output_tokens_per_minute.map(|l| Bucket::new(l, now)),
+                blocked_until: None,
+            })),
+        }
+    }
+
+    /// Waits until there is capacity for one request of the given size, then takes it.
+    pub async fn acquire(&self, input_tokens: u32, output_tokens: u32) {
+        loop {
+            let wait = {
+                let mut guard = self.state.lock().unwrap();
+                let state = &mut *guard;
+                let now = Instant::now();
+                let mut buckets = [
+                    (&mut state.requests, 1.0),
+                    (&mut state.input_tokens, input_tokens as f64),
+                    (&mut state.output_tokens, output_tokens as f64),
+                ];
+
+                let mut wait = state
+                    .blocked_until
+                    .map(|until| until.saturating_duration_since(now))
+                    .unwrap_or_default();
+                for (bucket, amount) in buckets.iter_mut() {
+                    if let Some(bucket) = bucket {
+                        bucket.refill(now);
+                        wait = wait.max(bucket.wait(*amount));
+                    }
+                }
+
+                if wait.is_zero() {
+                    for (bucket, amount) in buckets.iter_mut() {
+                        if let Some(bucket) = bucket {
+                            bucket.take(*amount);
+                        }
+                    }
+                    return;
+                }
+                wait
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Adjusts the buckets from the rate-limit headers of a response.
+    pub fn observe(&self, info: &RateLimitInfo) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        observe_bucket(&mut state.requests, &info.requests, now);
+        observe_bucket(&mut state.input_tokens, &info.input_tokens, now);
+        observe_bucket(&mut state.output_tokens, &info.output_tokens, now);
+        if let Some(retry_after) = info.retry_after {
+            let until = now + retry_after;
+            state.blocked_until = Some(state.blocked_until.map_or(until, |b| b.max(until)));
+        }
+    }
+}
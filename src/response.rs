@@ -0,0 +1,373 @@
+use std::fmt;
+use std::ops::Deref;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+
+use crate::types::AnthropicErrorMessage;
+
+/// One `anthropic-ratelimit-*` limit, as reported with a response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+    pub reset: Option<SystemTime>,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &HeaderMap, prefix: &str) -> Self {
+        Self {
+            limit: header_str(headers, &format!("{prefix}-limit")).and_then(|v| v.parse().ok()),
+            remaining: header_str(headers, &format!("{prefix}-remaining"))
+                .and_then(|v| v.parse().ok()),
+            reset: header_str(headers, &format!("{prefix}-reset")).and_then(parse_rfc3339),
+        }
+    }
+}
+
+/// Rate-limit state parsed from the response headers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimitInfo {
+    pub requests: RateLimit,
+    pub tokens: RateLimit,
+    pub input_tokens: RateLimit,
+    pub output_tokens: RateLimit,
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimitInfo {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Self {
+            requests: RateLimit::from_headers(headers, "anthropic-ratelimit-requests"),
+            tokens: RateLimit::from_headers(headers, "anthropic-ratelimit-tokens"),
+            input_tokens: RateLimit::from_headers(headers, "anthropic-ratelimit-input-tokens"),
+            output_tokens: RateLimit::from_headers(headers, "anthropic-ratelimit-output-tokens"),
+            retry_after: header_str(headers, "retry-after")
+                .and_then(|v| v.parse::<f64>().ok())
+                .map(Duration::from_secs_f64),
+        }
+    }
+}
+
+/// A response body together with the HTTP metadata it arrived with.
+#[derive(Debug, Clone)]
+pub struct Response<T> {
+    body: T,
+    model: Option<String>,
+    requested_model: String,
+    status: StatusCode,
+    headers: HeaderMap,
+    rate_limit: RateLimitInfo,
+    elapsed: Duration,
+}
+
+impl<T> Response<T> {
+    pub(crate) fn new(
+        body: T,
+        model: Option<String>,
+        requested_model: String,
+        status: StatusCode,
+        headers: HeaderMap,
+        started: Instant,
+    ) -> Self {
+        Self {
+            body,
+            model,
+            requested_model,
+            status,
+            rate_limit: RateLimitInfo::from_headers(&headers),
+            headers,
+            elapsed: started.elapsed(),
+        }
+    }
+
+    pub fn body(&self) -> &T {
+        &self.body
+    }
+
+    pub fn into_body(self) -> T {
+        self.body
+    }
+
+    /// The model that served the request, as the response reports it. Falls back
+    /// to [`requested_model`](Self::requested_model) if the response has none.
+    pub fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(&self.requested_model)
+    }
+
+    /// The model the request was sent to. After a fallback this is the fallback
+    /// model rather than the one the client was built with.
+    pub fn requested_model(&self) -> &str {
+        &self.requested_model
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The `request-id` header, which Anthropic support asks for.
+    pub fn request_id(&self) -> Option<&str> {
+        header_str(&self.headers, "request-id")
+    }
+
+    pub fn rate_limit(&self) -> &RateLimitInfo {
+        &self.rate_limit
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Time from sending the request until the body was fully read.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+impl<T> Deref for Response<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.body
+    }
+}
+
+/// Returned when the API answers with an error status. `error_type` and `message`
+/// come from the error body, such as `rate_limit_error`, when it has one.
+#[derive(Debug, Clone)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub error_type: Option<String>,
+    pub message: String,
+    /// The `request-id` header, which Anthropic support asks for.
+    pub request_id: Option<String>,
+    pub rate_limit: RateLimitInfo,
+}
+
+impl ApiError {
+    pub(crate) fn new(status: StatusCode, headers: &HeaderMap, body: &str) -> Self {
+        let (error_type, message) = match serde_json::from_str::<AnthropicErrorMessage>(body) {
+            Ok(error) => (Some(error.error.error_type), error.error.message),
+            Err(_) => (None, body.to_owned()),
+        };
+        Self {
+            status,
+            error_type,
+            message,
+            request_id: header_str(headers, "request-id").map(str::to_owned),
+            rate_limit: RateLimitInfo::from_headers(headers),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            StatusCode::BAD_REQUEST => write!(
+                f,
+                "Bad request. Check your request parameters. {}",
+                self.message
+            )?,
+            StatusCode::UNAUTHORIZED => write!(f, "Unauthorized. Check your authorization key.")?,
+            StatusCode::TOO_MANY_REQUESTS => {
+                write!(f, "Too many Requests. You have been rate limited.")?
+            }
+            status => write!(f, "Unexpected status code: {}: {}", status, self.message)?,
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, " (request-id: {request_id})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Parses timestamps such as `2024-05-01T12:00:30Z` or `2024-05-01T12:00:30.5+02:00`.
+fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.split_once(['T', ' '])?;
+
+    let mut date_parts = date.splitn(3, '-');
+    let year: i64 = date_parts.next()?.parse().ok()?;
+    let month: i64 = date_parts.next()?.parse().ok()?;
+    let day: i64 = date_parts.next()?.parse().ok()?;
+
+    let (clock, offset) = match time.strip_suffix(['Z', 'z']) {
+        Some(clock) => (clock, 0),
+        None => {
+            let index = time.rfind(['+', '-'])?;
+            let (clock, offset) = time.split_at(index);
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let offset = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+            (clock, sign * offset)
+        }
+    };
+
+    let mut clock_parts = clock.splitn(3, ':');
+    let hour: i64 = clock_parts.next()?.parse().ok()?;
+    let minute: i64 = clock_parts.next()?.parse().ok()?;
+    let second: f64 = clock_parts.next()?.parse().ok()?;
+
+    let seconds = (days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 - offset)
+        as f64
+        + second;
+    if seconds < 0.0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs_f64(seconds))
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(seconds: f64) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs_f64(seconds))
+    }
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn days_from_civil_counts_from_the_epoch() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+    }
+
+    #[test]
+    fn parses_utc_timestamps() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), at(0.0));
+        assert_eq!(parse_rfc3339("2024-05-01T12:00:30Z"), at(1_714_564_830.0));
+        assert_eq!(parse_rfc3339("2024-05-01 12:00:30z"), at(1_714_564_830.0));
+    }
+
+    #[test]
+    fn parses_fractional_seconds() {
+        assert_eq!(parse_rfc3339("2024-05-01T12:00:30.5Z"), at(1_714_564_830.5));
+        assert_eq!(
+            parse_rfc3339("2024-05-01T12:00:30.250Z"),
+            at(1_714_564_830.25)
+        );
+    }
+
+    #[test]
+    fn applies_offsets() {
+        assert_eq!(
+            parse_rfc3339("2024-05-01T14:00:30+02:00"),
+            at(1_714_564_830.0)
+        );
+        assert_eq!(
+            parse_rfc3339("2024-05-01T06:30:30.5-05:30"),
+            at(1_714_564_830.5)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_timestamps() {
+        for value in [
+            "",
+            "soon",
+            "2024-05-01",
+            "2024-05-01T12:00Z",
+            "2024-05-01T12:00:30",
+            "2024-05-01T12:00:30+0200",
+            "2024-May-01T12:00:30Z",
+            "1969-12-31T23:59:59Z",
+        ] {
+            assert_eq!(parse_rfc3339(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn reads_rate_limit_headers() {
+        let info = RateLimitInfo::from_headers(&headers(&[
+            ("anthropic-ratelimit-requests-limit", "50"),
+            ("anthropic-ratelimit-requests-remaining", "49"),
+            ("anthropic-ratelimit-requests-reset", "2024-05-01T12:00:30Z"),
+            ("anthropic-ratelimit-output-tokens-remaining", "8000"),
+            ("retry-after", "1.5"),
+        ]));
+
+        assert_eq!(
+            info.requests,
+            RateLimit {
+                limit: Some(50),
+                remaining: Some(49),
+                reset: at(1_714_564_830.0),
+            }
+        );
+        assert_eq!(info.output_tokens.remaining, Some(8000));
+        assert_eq!(info.tokens, RateLimit::default());
+        assert_eq!(info.retry_after, Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn missing_rate_limit_headers_are_none() {
+        assert_eq!(
+            RateLimitInfo::from_headers(&HeaderMap::new()),
+            RateLimitInfo::default()
+        );
+    }
+
+    #[test]
+    fn malformed_rate_limit_headers_are_none() {
+        let info = RateLimitInfo::from_headers(&headers(&[
+            ("anthropic-ratelimit-tokens-limit", "many"),
+            ("anthropic-ratelimit-tokens-remaining", "-1"),
+            ("anthropic-ratelimit-tokens-reset", "in a minute"),
+            ("retry-after", "Wed, 21 Oct 2015 07:28:00 GMT"),
+        ]));
+
+        assert_eq!(info, RateLimitInfo::default());
+    }
+
+    #[test]
+    fn api_error_reads_the_error_body_and_headers() {
+        let error = ApiError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            &headers(&[("request-id", "req_1"), ("retry-after", "3")]),
+            r#"{"type":"error","error":{"type":"rate_limit_error","message":"Slow down"}}"#,
+        );
+
+        assert_eq!(error.error_type.as_deref(), Some("rate_limit_error"));
+        assert_eq!(error.message, "Slow down");
+        assert_eq!(error.request_id.as_deref(), Some("req_1"));
+        assert_eq!(error.rate_limit.retry_after, Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn api_error_keeps_a_body_it_cannot_parse() {
+        let error = ApiError::new(StatusCode::BAD_GATEWAY, &HeaderMap::new(), "upstream down");
+
+        assert_eq!(error.error_type, None);
+        assert_eq!(error.message, "upstream down");
+        assert_eq!(
+            error.to_string(),
+            "Unexpected status code: 502 Bad Gateway: upstream down"
+        );
+    }
+}
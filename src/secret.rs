@@ -0,0 +1,47 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer};
+use zeroize::Zeroize;
+
+/// An API key. It is wiped from memory when dropped and its `Debug` output only
+/// shows the `sk-ant-` prefix.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub(crate) struct SecretKey(String);
+
+impl SecretKey {
+    pub fn new(key: &str) -> Self {
+        Self(key.to_owned())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretKey {
+    fn from(key: String) -> Self {
+        Self(key)
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+impl Drop for SecretKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            key if key.is_empty() => f.write_str("\"\""),
+            key if key.starts_with("sk-ant-") => f.write_str("sk-ant-…****"),
+            _ => f.write_str("****"),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use crate::metrics::StreamMetrics;
+use crate::types::{AnthropicChatCompletionChunk, AnthropicErrorMessage, AnthropicUsage};
+use crate::{AnthropicResponse, ContentItem, ServerToolUsage, Usage};
+
+/// What `Request::execute` gathered from a response, streamed or not. When a
+/// stream was resumed, `text` and `usage` cover every attempt and `recovered`
+/// counts the resumptions.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Completion {
+    pub id: Option<String>,
+    pub model: Option<String>,
+    pub text: String,
+    pub stop_reason: Option<String>,
+    pub usage: Usage,
+    #[serde(default)]
+    pub recovered: u32,
+    /// Set for streamed responses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<StreamMetrics>,
+    #[serde(skip)]
+    usage_before: Usage,
+    #[serde(skip)]
+    trim_next_text: bool,
+}
+
+impl Completion {
+    /// Folds one stream event into the completion, returning any new text.
+    pub(crate) fn apply(&mut self, event: &SseEvent) -> Result<Option<String>> {
+        if event.data.is_empty() || event.data == "[DONE]" {
+            return Ok(None);
+        }
+
+        let chunk = match serde_json::from_str::<AnthropicChatCompletionChunk>(&event.data) {
+            Ok(chunk) => chunk,
+            // Events this version doesn't know are skipped.
+            Err(_) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(data = %event.data, "Couldn't parse AnthropicChatCompletionChunk");
+                return Ok(None);
+            }
+        };
+
+        match chunk.event_type.as_str() {
+            "message_start" => {
+                if let Some(message) = chunk.message {
+                    self.id = message.id;
+                    self.model = message.model;
+                    // Usage adds up across the messages of a resumed stream.
+                    self.usage_before = self.usage.clone();
+                    if let Some(usage) = message.usage {
+                        merge_usage(&mut self.usage, &self.usage_before, usage);
+                    }
+                }
+            }
+            "content_block_delta" => {
+                if let Some(mut text) = chunk.delta.and_then(|delta| delta.text) {
+                    if self.trim_next_text {
+                        text = text.trim_start().to_owned();
+                        self.trim_next_text = text.is_empty();
+                    }
+                    if text.is_empty() {
+                        return Ok(None);
+                    }
+                    self.text.push_str(&text);
+                    return Ok(Some(text));
+                }
+            }
+            "message_delta" => {
+                if let Some(stop_reason) = chunk.delta.and_then(|delta| delta.stop_reason) {
+                    self.stop_reason = Some(stop_reason);
+                }
+                if let Some(usage) = chunk.usage {
+                    merge_usage(&mut self.usage, &self.usage_before, usage);
+                }
+            }
+            "error" => {
+                let error_message = serde_json::from_str::<AnthropicErrorMessage>(&event.data)
+                    .context("Failed to parse stream error")?;
+                return Err(StreamError {
+                    error_type: error_message.error.error_type,
+                    message: error_message.error.message,
+                }
+                .into());
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    /// Returns the prefill for resuming this completion. Prefills can't end in
+    /// whitespace, so any that is trimmed here is also dropped from the start of
+    /// the continuation, which already went to the callback.
+    pub(crate) fn prepare_resume(&mut self) -> String {
+        let prefill = self.text.trim_end().to_owned();
+        self.trim_next_text = prefill.len() != self.text.len();
+        self.recovered += 1;
+        prefill
+    }
+}
+
+/// Sets each field `usage` reports on top of `base`, the usage of any earlier
+/// messages in a resumed stream.
+fn merge_usage(total: &mut Usage, base: &Usage, usage: AnthropicUsage) {
+    let add = |base: Option<u32>, tokens: u32| Some(base.unwrap_or_default() + tokens);
+    if let Some(tokens) = usage.input_tokens {
+        total.input_tokens = base.input_tokens + tokens;
+    }
+    if let Some(tokens) = usage.output_tokens {
+        total.output_tokens = base.output_tokens + tokens;
+    }
+    if let Some(tokens) = usage.cache_creation_input_tokens {
+        total.cache_creation_input_tokens = add(base.cache_creation_input_tokens, tokens);
+    }
+    if let Some(tokens) = usage.cache_read_input_tokens {
+        total.cache_read_input_tokens = add(base.cache_read_input_tokens, tokens);
+    }
+    if let Some(server_tool_use) = usage.server_tool_use {
+        let before = base.server_tool_use.unwrap_or_default();
+        total.server_tool_use = Some(ServerToolUsage {
+            web_search_requests: before.web_search_requests + server_tool_use.web_search_requests,
+        });
+    }
+    if usage.service_tier.is_some() {
+        total.service_tier = usage.service_tier;
+    }
+}
+
+impl From<&AnthropicResponse> for Completion {
+    fn from(response: &AnthropicResponse) -> Self {
+        Self {
+            id: Some(response.id.clone()),
+            model: Some(response.model.clone()),
+            text: response
+                .content
+                .iter()
+                .filter_map(|item| match item {
+                    ContentItem::Text { text } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect(),
+            stop_reason: Some(response.stop_reason.clone()),
+            usage: response.usage.clone(),
+            ..Self::default()
+        }
+    }
+}
+
+/// An `error` event received in the middle of a stream, such as `overloaded_error`.
+#[derive(Debug, Clone)]
+pub struct StreamError {
+    pub error_type: String,
+    pub message: String,
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.error_type, self.message)
+    }
+}
+
+impl std::error::Error for StreamError {}
+
+/// Returned when a request is cancelled through its `CancellationToken`. `partial`
+/// holds the text and usage received before the connection was dropped.
+#[derive(Debug, Clone)]
+pub struct Cancelled {
+    pub partial: Completion,
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Request cancelled after {} characters of output",
+            self.partial.text.len()
+        )
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+pub(crate) async fn cancelled(token: &Option<CancellationToken>) {
+    match token {
+        Some(token) => token.cancelled().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// One server-sent event.
+pub(crate) struct SseEvent {
+    pub raw: String,
+    pub data: String,
+}
+
+/// Splits a byte stream into server-sent events. Bytes are buffered until an event
+/// is complete, so multi-byte characters split across chunks decode correctly.
+#[derive(Default)]
+pub(crate) struct SseParser {
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<SseEvent>> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(index) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let bytes: Vec<u8> = self.buffer.drain(..index + 2).take(index).collect();
+            let raw = String::from_utf8(bytes).context("Invalid UTF-8 sequence in stream")?;
+            let data = raw
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(str::trim_start)
+                .collect::<Vec<_>>()
+                .join("\n");
+            events.push(SseEvent { raw, data });
+        }
+        Ok(events)
+    }
+}
@@ -0,0 +1,313 @@
+//! Request instrumentation: `tracing` spans behind the `tracing` feature and
+//! Prometheus metrics behind the `prometheus` feature. Span attributes follow the
+//! OpenTelemetry GenAI semantic conventions; the few with no convention yet are
+//! prefixed with `anthropic.`.
+
+use std::future::Future;
+#[cfg(any(feature = "tracing", feature = "prometheus"))]
+use std::time::Instant;
+
+use reqwest::Response;
+use serde_json::Value;
+
+use crate::Completion;
+#[cfg(feature = "prometheus")]
+use crate::PrometheusMetrics;
+
+/// What a client asked to have recorded about its requests.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Telemetry {
+    pub capture_content: bool,
+    #[cfg(feature = "prometheus")]
+    pub prometheus: Option<PrometheusMetrics>,
+}
+
+/// Observes one call to `execute` or `execute_and_return_json`.
+pub(crate) struct RequestSpan {
+    #[cfg(feature = "tracing")]
+    trace: Trace,
+    #[cfg(feature = "prometheus")]
+    prometheus: Option<Prometheus>,
+}
+
+#[allow(unused_variables)]
+impl RequestSpan {
+    pub fn new(body: &Value, telemetry: &Telemetry) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            trace: Trace::new(body, telemetry.capture_content),
+            #[cfg(feature = "prometheus")]
+            prometheus: telemetry
+                .prometheus
+                .clone()
+                .map(|metrics| Prometheus::new(metrics, body)),
+        }
+    }
+
+    pub async fn instrument<F: Future>(&self, future: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        return self.trace.instrument(future).await;
+        #[cfg(not(feature = "tracing"))]
+        future.await
+    }
+
+    pub fn attempt(&self, attempt: u32, reason: &str) {
+        #[cfg(feature = "tracing")]
+        self.trace.attempt(attempt, reason);
+        #[cfg(feature = "prometheus")]
+        if let Some(prometheus) = &self.prometheus {
+            prometheus.metrics.retry(&prometheus.model.lock().unwrap());
+        }
+    }
+
+    /// Called with each response and the model it was requested from, which
+    /// differs from the client's model after a fallback.
+    pub fn response(&self, response: &Response, model: &str) {
+        #[cfg(feature = "tracing")]
+        self.trace.response(response);
+        #[cfg(feature = "prometheus")]
+        if let Some(prometheus) = &self.prometheus {
+            prometheus.response(response, model);
+        }
+    }
+
+    pub fn text(&self) {
+        #[cfg(feature = "tracing")]
+        self.trace.text();
+    }
+
+    pub fn finish(&self, outcome: Result<&Completion, &anyhow::Error>) {
+        #[cfg(feature = "tracing")]
+        self.trace.finish(outcome);
+        #[cfg(feature = "prometheus")]
+        if let Some(prometheus) = &self.prometheus {
+            prometheus.finish(outcome);
+        }
+    }
+}
+
+#[cfg(feature = "prometheus")]
+struct Prometheus {
+    metrics: PrometheusMetrics,
+    /// The model of the latest attempt, for requests that fail before a
+    /// response reports one.
+    model: std::sync::Mutex<String>,
+    started: Instant,
+    status: std::sync::atomic::AtomicU16,
+}
+
+#[cfg(feature = "prometheus")]
+impl Prometheus {
+    fn new(metrics: PrometheusMetrics, body: &Value) -> Self {
+        Self {
+            metrics,
+            model: std::sync::Mutex::new(body["model"].as_str().unwrap_or_default().to_owned()),
+            started: Instant::now(),
+            status: Default::default(),
+        }
+    }
+
+    fn response(&self, response: &Response, model: &str) {
+        use std::sync::atomic::Ordering;
+
+        self.status
+            .store(response.status().as_u16(), Ordering::Relaxed);
+        *self.model.lock().unwrap() = model.to_owned();
+        self.metrics
+            .rate_limit(&crate::RateLimitInfo::from_headers(response.headers()));
+    }
+
+    fn finish(&self, outcome: Result<&Completion, &anyhow::Error>) {
+        use std::sync::atomic::Ordering;
+
+        let status = match self.status.load(Ordering::Relaxed) {
+            0 => "none".to_owned(),
+            status => status.to_string(),
+        };
+        let (model, error_type) = match outcome {
+            Ok(completion) => (completion.model.clone(), "none".to_owned()),
+            Err(error) => (None, error_type(error)),
+        };
+        let model = model.unwrap_or_else(|| self.model.lock().unwrap().clone());
+        self.metrics.finish(
+            &model,
+            &status,
+            &error_type,
+            self.started.elapsed().as_secs_f64(),
+            outcome.ok(),
+        );
+    }
+}
+
+#[cfg(feature = "tracing")]
+struct Trace {
+    span: tracing::Span,
+    started: Instant,
+    capture_content: bool,
+    first_token: std::sync::atomic::AtomicBool,
+}
+
+#[cfg(feature = "tracing")]
+impl Trace {
+    fn new(body: &Value, capture_content: bool) -> Self {
+        use tracing::field::Empty;
+
+        let model = body["model"].as_str().unwrap_or_default();
+        let span = tracing::info_span!(
+            "chat",
+            otel.name = %format!("chat {model}"),
+            otel.kind = "client",
+            otel.status_code = Empty,
+            gen_ai.operation.name = "chat",
+            gen_ai.provider.name = "anthropic",
+            gen_ai.system = "anthropic",
+            gen_ai.request.model = model,
+            gen_ai.request.max_tokens = body["max_tokens"].as_u64(),
+            gen_ai.request.temperature = body["temperature"].as_f64(),
+            gen_ai.request.top_p = body["top_p"].as_f64(),
+            gen_ai.request.top_k = body["top_k"].as_i64(),
+            gen_ai.response.id = Empty,
+            gen_ai.response.model = Empty,
+            gen_ai.response.finish_reasons = Empty,
+            gen_ai.usage.input_tokens = Empty,
+            gen_ai.usage.output_tokens = Empty,
+            gen_ai.usage.cache_read.input_tokens = Empty,
+            gen_ai.usage.cache_creation.input_tokens = Empty,
+            gen_ai.input.messages = Empty,
+            gen_ai.system_instructions = Empty,
+            gen_ai.output.messages = Empty,
+            http.response.status_code = Empty,
+            http.request.resend_count = Empty,
+            "error.type" = Empty,
+            anthropic.request_id = Empty,
+            anthropic.time_to_first_token_ms = Empty,
+            anthropic.latency_ms = Empty,
+        );
+
+        if capture_content {
+            span.record(
+                "gen_ai.input.messages",
+                body["messages"].to_string().as_str(),
+            );
+            if !body["system"].is_null() {
+                span.record(
+                    "gen_ai.system_instructions",
+                    body["system"].to_string().as_str(),
+                );
+            }
+        }
+
+        Self {
+            span,
+            started: Instant::now(),
+            capture_content,
+            first_token: Default::default(),
+        }
+    }
+
+    async fn instrument<F: Future>(&self, future: F) -> F::Output {
+        use tracing::Instrument;
+
+        future.instrument(self.span.clone()).await
+    }
+
+    fn attempt(&self, attempt: u32, reason: &str) {
+        self.span.record("http.request.resend_count", attempt);
+        tracing::warn!(parent: &self.span, attempt, reason, "retrying request");
+    }
+
+    fn response(&self, response: &Response) {
+        self.span
+            .record("http.response.status_code", response.status().as_u16());
+        if let Some(request_id) = response
+            .headers()
+            .get("request-id")
+            .and_then(|value| value.to_str().ok())
+        {
+            self.span.record("anthropic.request_id", request_id);
+        }
+    }
+
+    fn text(&self) {
+        use std::sync::atomic::Ordering;
+
+        if !self.first_token.swap(true, Ordering::Relaxed) {
+            self.span.record(
+                "anthropic.time_to_first_token_ms",
+                self.started.elapsed().as_millis() as u64,
+            );
+        }
+    }
+
+    fn finish(&self, outcome: Result<&Completion, &anyhow::Error>) {
+        self.span.record(
+            "anthropic.latency_ms",
+            self.started.elapsed().as_millis() as u64,
+        );
+
+        match outcome {
+            Ok(completion) => {
+                let usage = &completion.usage;
+                self.span
+                    .record("gen_ai.response.id", completion.id.as_deref());
+                self.span
+                    .record("gen_ai.response.model", completion.model.as_deref());
+                if let Some(stop_reason) = &completion.stop_reason {
+                    self.span.record(
+                        "gen_ai.response.finish_reasons",
+                        format!("[\"{stop_reason}\"]").as_str(),
+                    );
+                }
+                self.span
+                    .record("gen_ai.usage.input_tokens", usage.input_tokens);
+                self.span
+                    .record("gen_ai.usage.output_tokens", usage.output_tokens);
+                self.span.record(
+                    "gen_ai.usage.cache_read.input_tokens",
+                    usage.cache_read_input_tokens,
+                );
+                self.span.record(
+                    "gen_ai.usage.cache_creation.input_tokens",
+                    usage.cache_creation_input_tokens,
+                );
+                if self.capture_content {
+                    let output = serde_json::json!([{
+                        "role": "assistant",
+                        "content": completion.text,
+                    }]);
+                    self.span
+                        .record("gen_ai.output.messages", output.to_string().as_str());
+                }
+            }
+            Err(error) => {
+                self.span.record("otel.status_code", "ERROR");
+                self.span.record("error.type", error_type(error));
+                tracing::error!(parent: &self.span, error = %error, "request failed");
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "tracing", feature = "prometheus"))]
+fn error_type(error: &anyhow::Error) -> String {
+    use crate::{ApiError, BudgetExceeded, Cancelled, StreamError, Timeout, ValidationError};
+
+    if let Some(api_error) = error.downcast_ref::<ApiError>() {
+        api_error
+            .error_type
+            .clone()
+            .unwrap_or_else(|| api_error.status.as_u16().to_string())
+    } else if let Some(stream_error) = error.downcast_ref::<StreamError>() {
+        stream_error.error_type.clone()
+    } else if error.is::<Timeout>() {
+        "timeout".to_owned()
+    } else if error.is::<Cancelled>() {
+        "cancelled".to_owned()
+    } else if error.is::<BudgetExceeded>() {
+        "budget_exceeded".to_owned()
+    } else if error.is::<ValidationError>() {
+        "invalid_request".to_owned()
+    } else {
+        "_OTHER".to_owned()
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use tokio_util::sync::CancellationToken;
+
+use crate::stream::{self, Cancelled, Completion};
+
+/// The part of a request that stalled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Opening the TCP/TLS connection.
+    Connect,
+    /// Waiting for the response headers after sending the request.
+    FirstByte,
+    /// Waiting for the next stream event. `ping` events count.
+    Idle,
+    /// The request as a whole, including retries.
+    Total,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connect => write!(f, "connect"),
+            Self::FirstByte => write!(f, "first byte"),
+            Self::Idle => write!(f, "idle"),
+            Self::Total => write!(f, "total"),
+        }
+    }
+}
+
+/// Returned when one of the configured deadlines passes. `partial` holds anything
+/// received before the request stalled.
+#[derive(Debug, Clone)]
+pub struct Timeout {
+    pub phase: TimeoutPhase,
+    pub after: Duration,
+    pub partial: Completion,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Request timed out: {} deadline of {:?} passed",
+            self.phase, self.after
+        )
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Timeouts {
+    pub connect: Option<Duration>,
+    pub first_byte: Option<Duration>,
+    pub idle: Option<Duration>,
+    pub total: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Deadline {
+    at: Instant,
+    phase: TimeoutPhase,
+    after: Duration,
+}
+
+impl Deadline {
+    pub fn after(phase: TimeoutPhase, after: Option<Duration>, from: Instant) -> Option<Self> {
+        after.map(|after| Self {
+            at: from + after,
+            phase,
+            after,
+        })
+    }
+
+    pub fn earliest(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(if a.at <= b.at { a } else { b }),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+async fn expired(deadline: Option<Deadline>) -> Deadline {
+    match deadline {
+        Some(deadline) => {
+            tokio::time::sleep_until(deadline.at.into()).await;
+            deadline
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Runs `future` unless the request is cancelled or `deadline` passes first.
+pub(crate) async fn guard<T, F>(
+    future: F,
+    cancellation: &Option<CancellationToken>,
+    deadline: Option<Deadline>,
+    partial: &Completion,
+) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    tokio::select! {
+        result = future => result,
+        _ = stream::cancelled(cancellation) => Err(Cancelled { partial: partial.clone() }.into()),
+        deadline = expired(deadline) => Err(Timeout {
+            phase: deadline.phase,
+            after: deadline.after,
+            partial: partial.clone(),
+        }
+        .into()),
+    }
+}
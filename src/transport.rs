@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use futures::stream::{self, Stream, StreamExt};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::{json, Value};
+
+use crate::HttpRequest;
+
+/// Sends HTTP requests for a client. The default sends them with reqwest;
+/// [`MockTransport`] answers from a script instead, for tests.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn send(&self, request: reqwest::Request) -> Result<reqwest::Response>;
+
+    /// For transports that wrap another, such as a recording [`Cassette`]: a
+    /// copy that sends through `inner`, the transport the client would use
+    /// without this one. Returns `None` by default.
+    ///
+    /// [`Cassette`]: crate::Cassette
+    fn with_inner(&self, inner: ReqwestTransport) -> Option<Arc<dyn Transport>> {
+        let _ = inner;
+        None
+    }
+}
+
+/// The default transport.
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl Transport for ReqwestTransport {
+    async fn send(&self, request: reqwest::Request) -> Result<reqwest::Response> {
+        Ok(self.client.execute(request).await?)
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct SharedTransport(pub Arc<dyn Transport>);
+
+impl fmt::Debug for SharedTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Transport")
+    }
+}
+
+/// A scripted response for [`MockTransport`].
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    chunks: Vec<Vec<u8>>,
+    delay: Duration,
+}
+
+impl MockResponse {
+    pub fn json(status: u16, body: &Value) -> Self {
+        Self {
+            status,
+            headers: vec![("content-type".to_owned(), "application/json".to_owned())],
+            chunks: vec![body.to_string().into_bytes()],
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// A complete, non-streamed message with `text` as its only content.
+    pub fn message(text: &str) -> Self {
+        Self::json(
+            200,
+            &json!({
+                "id": "msg_mock",
+                "type": "message",
+                "role": "assistant",
+                "model": "mock",
+                "content": [{"type": "text", "text": text}],
+                "stop_reason": "end_turn",
+                "stop_sequence": null,
+                "usage": {"input_tokens": 0, "output_tokens": 0},
+            }),
+        )
+    }
+
+    /// An API error body, such as `MockResponse::error(529, "overloaded_error", "Overloaded")`.
+    pub fn error(status: u16, error_type: &str, message: &str) -> Self {
+        Self::json(
+            status,
+            &json!({"type": "error", "error": {"type": error_type, "message": message}}),
+        )
+    }
+
+    /// A 200 event stream sending each of `events` as one chunk.
+    pub fn sse(events: &[Value]) -> Self {
+        Self {
+            status: 200,
+            headers: vec![("content-type".to_owned(), "text/event-stream".to_owned())],
+            chunks: events
+                .iter()
+                .map(|event| {
+                    let event_type = event["type"].as_str().unwrap_or("message");
+                    format!("event: {event_type}\ndata: {event}\n\n").into_bytes()
+                })
+                .collect(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// A complete stream with one text delta per item of `texts`.
+    pub fn text_stream(texts: &[&str]) -> Self {
+        let mut events = vec![
+            json!({"type": "message_start", "message": {
+                "id": "msg_mock", "type": "message", "role": "assistant", "model": "mock",
+                "content": [], "usage": {"input_tokens": 0, "output_tokens": 0},
+            }}),
+            json!({"type": "content_block_start", "index": 0,
+                "content_block": {"type": "text", "text": ""}}),
+        ];
+        events.extend(texts.iter().map(|text| {
+            json!({"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": text}})
+        }));
+        events.extend([
+            json!({"type": "content_block_stop", "index": 0}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"},
+                "usage": {"output_tokens": texts.len()}}),
+            json!({"type": "message_stop"}),
+        ]);
+        Self::sse(&events)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Waits `delay` before sending each chunk of the body.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Sends `chunks` as the body, exactly as given.
+    pub fn chunks(mut self, chunks: Vec<Vec<u8>>) -> Self {
+        self.chunks = chunks;
+        self
+    }
+
+    fn into_response(self) -> Result<reqwest::Response> {
+        let delay = self.delay;
+        let body = stream::iter(self.chunks).then(move |chunk| async move {
+            tokio::time::sleep(delay).await;
+            Ok(chunk)
+        });
+        build_response(self.status, &self.headers, body)
+    }
+}
+
+/// A response whose body is read from `body` as the caller polls it.
+pub(crate) fn build_response<S>(
+    status: u16,
+    headers: &[(String, String)],
+    body: S,
+) -> Result<reqwest::Response>
+where
+    S: Stream<Item = std::io::Result<Vec<u8>>> + Send + Sync + 'static,
+{
+    let mut header_map = HeaderMap::new();
+    for (name, value) in headers {
+        header_map.append(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+
+    let mut response = http::Response::builder()
+        .status(status)
+        .body(reqwest::Body::wrap_stream(body))?;
+    *response.headers_mut() = header_map;
+    Ok(response.into())
+}
+
+#[derive(Default)]
+struct MockState {
+    responses: VecDeque<MockResponse>,
+    requests: Vec<HttpRequest>,
+}
+
+/// A [`Transport`] that answers requests with scripted responses, in order, and
+/// keeps the requests it received. Clones share the script.
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl fmt::Debug for MockTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("MockTransport")
+            .field("responses", &state.responses.len())
+            .field("requests", &state.requests.len())
+            .finish()
+    }
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `response` as the answer to the next unanswered request.
+    pub fn reply(self, response: MockResponse) -> Self {
+        self.state.lock().unwrap().responses.push_back(response);
+        self
+    }
+
+    /// Every request received so far.
+    pub fn requests(&self) -> Vec<HttpRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+#[async_trait]
+impl Transport for MockTransport {
+    async fn send(&self, request: reqwest::Request) -> Result<reqwest::Response> {
+        let body = match request.body().and_then(reqwest::Body::as_bytes) {
+            Some(bytes) => serde_json::from_slice(bytes).context("Request body is not JSON")?,
+            None => Value::Null,
+        };
+        let response = {
+            let mut state = self.state.lock().unwrap();
+            state.requests.push(HttpRequest {
+                method: request.method().clone(),
+                url: request.url().clone(),
+                headers: request.headers().clone(),
+                body,
+            });
+            state.responses.pop_front()
+        };
+
+        response
+            .ok_or_else(|| anyhow!("MockTransport has no response left for {}", request.url()))?
+            .into_response()
+    }
+}
@@ -1,9 +1,15 @@
 use serde::{Deserialize, Serialize};
 
+use crate::ServerToolUsage;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AnthropicUsage {
     pub input_tokens: Option<u32>,
     pub output_tokens: Option<u32>,
+    pub cache_creation_input_tokens: Option<u32>,
+    pub cache_read_input_tokens: Option<u32>,
+    pub server_tool_use: Option<ServerToolUsage>,
+    pub service_tier: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -43,7 +49,8 @@ pub struct AnthropicChatCompletionChunk {
     pub event_type: String,
     pub index: Option<usize>,
     pub delta: Option<AnthropicTextDelta>,
-    pub message: Option<AnthropicMessage>
+    pub message: Option<AnthropicMessage>,
+    pub usage: Option<AnthropicUsage>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -59,4 +66,4 @@ pub struct AnthropicErrorDetails {
     #[serde(rename = "type")]
     pub error_type: String,
     pub message: String,
-}
\ No newline at end of file
+}
@@ -0,0 +1,474 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::context::estimate_input_tokens;
+use crate::{Model, Usage};
+
+/// US dollars per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+    pub cache_write: f64,
+    pub cache_read: f64,
+}
+
+impl Price {
+    /// A price with the usual cache multipliers: writes at 1.25x and reads at
+    /// 0.1x the input price.
+    pub fn new(input: f64, output: f64) -> Self {
+        Self {
+            input,
+            output,
+            cache_write: input * 1.25,
+            cache_read: input * 0.1,
+        }
+    }
+}
+
+/// Prices per model, keyed by snapshot ID so aliases share a price. The default
+/// table holds list prices and can be changed with [`PriceTable::set`].
+#[derive(Debug, Clone)]
+pub struct PriceTable {
+    prices: HashMap<String, Price>,
+    web_search_per_thousand: f64,
+    batch_discount: f64,
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        let opus = Price::new(15.0, 75.0);
+        let sonnet = Price::new(3.0, 15.0);
+        let prices = [
+            ("claude-3-haiku-20240307", Price::new(0.25, 1.25)),
+            ("claude-3-opus-20240229", opus),
+            ("claude-3-5-sonnet-20240620", sonnet),
+            ("claude-3-5-sonnet-20241022", sonnet),
+            ("claude-3-5-haiku-20241022", Price::new(0.8, 4.0)),
+            ("claude-3-7-sonnet-20250219", sonnet),
+            ("claude-sonnet-4-20250514", sonnet),
+            ("claude-opus-4-20250514", opus),
+            ("claude-opus-4-1-20250805", opus),
+            ("claude-sonnet-4-5-20250929", sonnet),
+            ("claude-haiku-4-5-20251001", Price::new(1.0, 5.0)),
+            ("claude-opus-4-5-20251101", Price::new(5.0, 25.0)),
+        ];
+
+        Self {
+            prices: prices
+                .into_iter()
+                .map(|(model, price)| (model.to_owned(), price))
+                .collect(),
+            web_search_per_thousand: 10.0,
+            batch_discount: 0.5,
+        }
+    }
+}
+
+impl PriceTable {
+    /// A table with no model prices, for callers who supply all of them.
+    pub fn empty() -> Self {
+        Self {
+            prices: HashMap::new(),
+            ..Self::default()
+        }
+    }
+
+    pub fn set(mut self, model: &str, price: Price) -> Self {
+        self.prices.insert(resolve(model), price);
+        self
+    }
+
+    /// The price of 1,000 web searches. Defaults to $10.
+    pub fn web_search(mut self, per_thousand: f64) -> Self {
+        self.web_search_per_thousand = per_thousand;
+        self
+    }
+
+    /// The share taken off token prices on the `batch` service tier. Defaults to 0.5.
+    pub fn batch_discount(mut self, batch_discount: f64) -> Self {
+        self.batch_discount = batch_discount;
+        self
+    }
+
+    pub fn price(&self, model: &str) -> Option<Price> {
+        self.prices
+            .get(model)
+            .or_else(|| self.prices.get(&resolve(model)))
+            .copied()
+    }
+
+    /// The cost of `usage` in US dollars, or `None` if `model` has no price.
+    pub fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+        let price = self.price(model)?;
+        let mut tokens = f64::from(usage.input_tokens) * price.input
+            + f64::from(usage.output_tokens) * price.output
+            + f64::from(usage.cache_creation_input_tokens.unwrap_or_default()) * price.cache_write
+            + f64::from(usage.cache_read_input_tokens.unwrap_or_default()) * price.cache_read;
+        if usage.service_tier.as_deref() == Some("batch") {
+            tokens *= 1.0 - self.batch_discount;
+        }
+        let web_searches = usage
+            .server_tool_use
+            .map_or(0, |server_tool_use| server_tool_use.web_search_requests);
+
+        Some(tokens / 1_000_000.0 + f64::from(web_searches) * self.web_search_per_thousand / 1000.0)
+    }
+}
+
+fn resolve(model: &str) -> String {
+    Model::lookup(model).map_or_else(|| model.to_owned(), |model| model.id)
+}
+
+/// Usage summed over a set of requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_creation_input_tokens: u64,
+    pub cache_read_input_tokens: u64,
+    pub web_search_requests: u64,
+    pub batch_requests: u64,
+    pub priority_requests: u64,
+    /// Requests whose model isn't in the price table, and so aren't in `cost_usd`.
+    pub unpriced_requests: u64,
+    pub cost_usd: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, other: &UsageTotals) {
+        self.requests += other.requests;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_creation_input_tokens += other.cache_creation_input_tokens;
+        self.cache_read_input_tokens += other.cache_read_input_tokens;
+        self.web_search_requests += other.web_search_requests;
+        self.batch_requests += other.batch_requests;
+        self.priority_requests += other.priority_requests;
+        self.unpriced_requests += other.unpriced_requests;
+        self.cost_usd += other.cost_usd;
+    }
+}
+
+/// The totals for one model and tag.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageRow {
+    pub model: String,
+    pub tag: Option<String>,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+type BudgetCallback = Arc<dyn Fn(&BudgetExceeded) + Send + Sync>;
+
+/// A spending limit in US dollars on everything a [`UsageTracker`] records, or on
+/// one tag. A hard budget refuses requests whose estimated cost would go past it;
+/// a soft budget calls back once when spending crosses it.
+#[derive(Clone)]
+pub struct Budget {
+    limit_usd: f64,
+    tag: Option<String>,
+    on_exceeded: Option<BudgetCallback>,
+}
+
+impl fmt::Debug for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Budget")
+            .field("limit_usd", &self.limit_usd)
+            .field("tag", &self.tag)
+            .field("hard", &self.on_exceeded.is_none())
+            .finish()
+    }
+}
+
+impl Budget {
+    pub fn hard(limit_usd: f64) -> Self {
+        Self {
+            limit_usd,
+            tag: None,
+            on_exceeded: None,
+        }
+    }
+
+    pub fn soft<F>(limit_usd: f64, on_exceeded: F) -> Self
+    where
+        F: Fn(&BudgetExceeded) + Send + Sync + 'static,
+    {
+        Self {
+            limit_usd,
+            tag: None,
+            on_exceeded: Some(Arc::new(on_exceeded)),
+        }
+    }
+
+    /// Only counts, and only limits, requests with this tag.
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.tag = Some(tag.to_owned());
+        self
+    }
+
+    fn covers(&self, tag: Option<&str>) -> bool {
+        self.tag.is_none() || self.tag.as_deref() == tag
+    }
+}
+
+/// Returned by `Client::build` and `Request::execute` when a hard [`Budget`]
+/// would be exceeded, and passed to soft budget callbacks.
+#[derive(Debug, Clone)]
+pub struct BudgetExceeded {
+    pub tag: Option<String>,
+    pub limit_usd: f64,
+    pub spent_usd: f64,
+    /// The estimated cost of the refused request. Zero for soft budgets.
+    pub estimated_usd: f64,
+}
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Budget of ${:.2}", self.limit_usd)?;
+        if let Some(tag) = &self.tag {
+            write!(f, " for {tag}")?;
+        }
+        write!(
+            f,
+            " exceeded: ${:.4} spent, ${:.4} estimated for the next request",
+            self.spent_usd, self.estimated_usd
+        )
+    }
+}
+
+impl std::error::Error for BudgetExceeded {}
+
+#[derive(Debug)]
+struct State {
+    prices: PriceTable,
+    totals: BTreeMap<(String, Option<String>), UsageTotals>,
+    budgets: Vec<Budget>,
+}
+
+impl State {
+    fn spent(&self, budget: &Budget) -> f64 {
+        self.totals
+            .iter()
+            .filter(|((_, tag), _)| budget.covers(tag.as_deref()))
+            .map(|(_, totals)| totals.cost_usd)
+            .sum()
+    }
+}
+
+/// Adds up the usage and cost of every request sent by the clients it is passed
+/// to, keyed by model and by the tag set with `Client::usage_tag`. Clones share
+/// their totals.
+#[derive(Debug, Clone)]
+pub struct UsageTracker {
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for UsageTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self::with_prices(PriceTable::default())
+    }
+
+    pub fn with_prices(prices: PriceTable) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                prices,
+                totals: BTreeMap::new(),
+                budgets: Vec::new(),
+            })),
+        }
+    }
+
+    /// Adds a spending limit. Budgets are shared by every clone of the tracker.
+    pub fn budget(self, budget: Budget) -> Self {
+        self.state.lock().unwrap().budgets.push(budget);
+        self
+    }
+
+    /// Fails with [`BudgetExceeded`] if sending `body` could take a hard budget
+    /// past its limit, estimating the cost from the input size plus `max_tokens`.
+    pub(crate) fn check(&self, body: &Value, tag: Option<&str>) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        let estimate = Usage {
+            input_tokens: estimate_input_tokens(body),
+            output_tokens: body["max_tokens"].as_u64().unwrap_or_default() as u32,
+            ..Usage::default()
+        };
+        let model = body["model"].as_str().unwrap_or_default();
+        let estimated_usd = state.prices.cost(model, &estimate).unwrap_or_default();
+
+        for budget in &state.budgets {
+            if budget.on_exceeded.is_some() || !budget.covers(tag) {
+                continue;
+            }
+            let spent_usd = state.spent(budget);
+            if spent_usd + estimated_usd > budget.limit_usd {
+                return Err(BudgetExceeded {
+                    tag: budget.tag.clone(),
+                    limit_usd: budget.limit_usd,
+                    spent_usd,
+                    estimated_usd,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds one response's usage. Called by `Request::execute` and
+    /// `Request::execute_and_return_json`; call it directly for usage from
+    /// elsewhere, such as batch results.
+    pub fn record(&self, model: &str, tag: Option<&str>, usage: &Usage) {
+        let mut state = self.state.lock().unwrap();
+        let soft_budgets: Vec<_> = state
+            .budgets
+            .iter()
+            .filter(|budget| budget.on_exceeded.is_some() && budget.covers(tag))
+            .map(|budget| (budget.clone(), state.spent(budget)))
+            .collect();
+        let cost = state.prices.cost(model, usage);
+        let tier = usage.service_tier.as_deref();
+        let totals = UsageTotals {
+            requests: 1,
+            input_tokens: u64::from(usage.input_tokens),
+            output_tokens: u64::from(usage.output_tokens),
+            cache_creation_input_tokens: u64::from(
+                usage.cache_creation_input_tokens.unwrap_or_default(),
+            ),
+            cache_read_input_tokens: u64::from(usage.cache_read_input_tokens.unwrap_or_default()),
+            web_search_requests: u64::from(
+                usage
+                    .server_tool_use
+                    .map_or(0, |server_tool_use| server_tool_use.web_search_requests),
+            ),
+            batch_requests: u64::from(tier == Some("batch")),
+            priority_requests: u64::from(tier == Some("priority")),
+            unpriced_requests: u64::from(cost.is_none()),
+            cost_usd: cost.unwrap_or_default(),
+        };
+
+        state
+            .totals
+            .entry((model.to_owned(), tag.map(str::to_owned)))
+            .or_default()
+            .add(&totals);
+
+        let crossed: Vec<_> = soft_budgets
+            .into_iter()
+            .filter(|(budget, before)| *before <= budget.limit_usd)
+            .filter_map(|(budget, _)| {
+                let spent_usd = state.spent(&budget);
+                (spent_usd > budget.limit_usd).then_some((budget, spent_usd))
+            })
+            .collect();
+        drop(state);
+
+        for (budget, spent_usd) in crossed {
+            if let Some(on_exceeded) = &budget.on_exceeded {
+                on_exceeded(&BudgetExceeded {
+                    tag: budget.tag.clone(),
+                    limit_usd: budget.limit_usd,
+                    spent_usd,
+                    estimated_usd: 0.0,
+                });
+            }
+        }
+    }
+
+    /// One row per model and tag, sorted by model.
+    pub fn rows(&self) -> Vec<UsageRow> {
+        let state = self.state.lock().unwrap();
+        state
+            .totals
+            .iter()
+            .map(|((model, tag), totals)| UsageRow {
+                model: model.clone(),
+                tag: tag.clone(),
+                totals: *totals,
+            })
+            .collect()
+    }
+
+    pub fn total(&self) -> UsageTotals {
+        let mut total = UsageTotals::default();
+        for row in self.rows() {
+            total.add(&row.totals);
+        }
+        total
+    }
+
+    pub fn by_model(&self) -> BTreeMap<String, UsageTotals> {
+        let mut by_model = BTreeMap::<String, UsageTotals>::new();
+        for row in self.rows() {
+            by_model.entry(row.model).or_default().add(&row.totals);
+        }
+        by_model
+    }
+
+    /// Totals per tag. Untagged requests are under `None`.
+    pub fn by_tag(&self) -> BTreeMap<Option<String>, UsageTotals> {
+        let mut by_tag = BTreeMap::<Option<String>, UsageTotals>::new();
+        for row in self.rows() {
+            by_tag.entry(row.tag).or_default().add(&row.totals);
+        }
+        by_tag
+    }
+
+    pub fn reset(&self) {
+        self.state.lock().unwrap().totals.clear();
+    }
+
+    /// The rows as a JSON array.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.rows())?)
+    }
+
+    /// The rows as CSV with a header line.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "model,tag,requests,input_tokens,output_tokens,cache_creation_input_tokens,\
+             cache_read_input_tokens,web_search_requests,batch_requests,priority_requests,\
+             unpriced_requests,cost_usd\n",
+        );
+        for row in self.rows() {
+            let totals = row.totals;
+            let _ = writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{},{},{},{},{:.6}",
+                csv_field(&row.model),
+                csv_field(row.tag.as_deref().unwrap_or_default()),
+                totals.requests,
+                totals.input_tokens,
+                totals.output_tokens,
+                totals.cache_creation_input_tokens,
+                totals.cache_read_input_tokens,
+                totals.web_search_requests,
+                totals.batch_requests,
+                totals.priority_requests,
+                totals.unpriced_requests,
+                totals.cost_usd,
+            );
+        }
+        csv
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::Model;
+
+/// The smallest `budget_tokens` the API accepts for extended thinking.
+const MIN_THINKING_BUDGET: u64 = 1024;
+
+/// A problem found in a request body before it is sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+    EmptyModel,
+    ZeroMaxTokens,
+    MaxTokensAboveLimit {
+        model: String,
+        max_tokens: u64,
+        limit: u32,
+    },
+    TemperatureOutOfRange(f64),
+    TopPOutOfRange(f64),
+    TopKWithTopP {
+        model: String,
+    },
+    ThinkingUnsupported {
+        model: String,
+    },
+    ThinkingBudget {
+        budget_tokens: u64,
+        max_tokens: u64,
+    },
+    EmptyMessages,
+    AssistantFirst,
+    ConsecutiveRole {
+        index: usize,
+        role: String,
+    },
+    UnknownToolUseId {
+        index: usize,
+        tool_use_id: String,
+    },
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyModel => write!(f, "model is empty"),
+            Self::ZeroMaxTokens => write!(f, "max_tokens must be greater than 0"),
+            Self::MaxTokensAboveLimit {
+                model,
+                max_tokens,
+                limit,
+            } => write!(
+                f,
+                "max_tokens {max_tokens} is above the {limit} output tokens {model} allows"
+            ),
+            Self::TemperatureOutOfRange(temperature) => {
+                write!(f, "temperature {temperature} is outside 0.0..=1.0")
+            }
+            Self::TopPOutOfRange(top_p) => write!(f, "top_p {top_p} is outside 0.0..=1.0"),
+            Self::TopKWithTopP { model } => {
+                write!(f, "{model} does not accept top_k and top_p together")
+            }
+            Self::ThinkingUnsupported { model } => {
+                write!(f, "{model} does not support extended thinking")
+            }
+            Self::ThinkingBudget {
+                budget_tokens,
+                max_tokens,
+            } => write!(
+                f,
+                "thinking budget_tokens {budget_tokens} must be at least {MIN_THINKING_BUDGET} and below max_tokens {max_tokens}"
+            ),
+            Self::EmptyMessages => write!(f, "messages is empty"),
+            Self::AssistantFirst => write!(f, "the first message must have the user role"),
+            Self::ConsecutiveRole { index, role } => {
+                write!(f, "messages[{index}] repeats the {role} role of the message before it")
+            }
+            Self::UnknownToolUseId { index, tool_use_id } => write!(
+                f,
+                "messages[{index}] has a tool_result for {tool_use_id}, which no earlier tool_use has"
+            ),
+        }
+    }
+}
+
+/// Returned by `Client::build` when the request fails validation.
+#[derive(Debug, Clone)]
+pub struct ValidationError {
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid request: ")?;
+        for (n, issue) in self.issues.iter().enumerate() {
+            if n > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{issue}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+pub(crate) fn validate(body: &Value) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+
+    let model = body["model"].as_str().unwrap_or_default();
+    if model.trim().is_empty() {
+        issues.push(ValidationIssue::EmptyModel);
+    }
+
+    // Unknown models are let through, since the catalog may lag behind the API.
+    let info = Model::lookup(model);
+
+    let max_tokens = body["max_tokens"].as_u64().unwrap_or_default();
+    if max_tokens == 0 {
+        issues.push(ValidationIssue::ZeroMaxTokens);
+    }
+    if let Some(info) = &info {
+        if max_tokens > u64::from(info.max_output_tokens) {
+            issues.push(ValidationIssue::MaxTokensAboveLimit {
+                model: model.to_owned(),
+                max_tokens,
+                limit: info.max_output_tokens,
+            });
+        }
+    }
+
+    if let Some(temperature) = body["temperature"].as_f64() {
+        if !(0.0..=1.0).contains(&temperature) {
+            issues.push(ValidationIssue::TemperatureOutOfRange(temperature));
+        }
+    }
+
+    if let Some(top_p) = body["top_p"].as_f64() {
+        if !(0.0..=1.0).contains(&top_p) {
+            issues.push(ValidationIssue::TopPOutOfRange(top_p));
+        }
+    }
+
+    if !body["top_k"].is_null()
+        && !body["top_p"].is_null()
+        && info.as_ref().is_some_and(|info| info.exclusive_sampling)
+    {
+        issues.push(ValidationIssue::TopKWithTopP {
+            model: model.to_owned(),
+        });
+    }
+
+    if let Some(budget_tokens) = body["thinking"]["budget_tokens"].as_u64() {
+        if info.as_ref().is_some_and(|info| !info.extended_thinking) {
+            issues.push(ValidationIssue::ThinkingUnsupported {
+                model: model.to_owned(),
+            });
+        }
+        if budget_tokens < MIN_THINKING_BUDGET || budget_tokens >= max_tokens {
+            issues.push(ValidationIssue::ThinkingBudget {
+                budget_tokens,
+                max_tokens,
+            });
+        }
+    }
+
+    let messages = body["messages"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or(&[]);
+    if messages.is_empty() {
+        issues.push(ValidationIssue::EmptyMessages);
+    }
+
+    let mut tool_use_ids = HashSet::new();
+    for (index, message) in messages.iter().enumerate() {
+        let role = message["role"].as_str().unwrap_or_default();
+        if index == 0 && role == "assistant" {
+            issues.push(ValidationIssue::AssistantFirst);
+        }
+        if index > 0 && messages[index - 1]["role"].as_str() == Some(role) {
+            issues.push(ValidationIssue::ConsecutiveRole {
+                index,
+                role: role.to_owned(),
+            });
+        }
+
+        for block in message["content"]
+            .as_array()
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+        {
+            match block["type"].as_str() {
+                Some("tool_use") => {
+                    if let Some(id) = block["id"].as_str() {
+                        tool_use_ids.insert(id);
+                    }
+                }
+                Some("tool_result") => {
+                    let tool_use_id = block["tool_use_id"].as_str().unwrap_or_default();
+                    if !tool_use_ids.contains(tool_use_id) {
+                        issues.push(ValidationIssue::UnknownToolUseId {
+                            index,
+                            tool_use_id: tool_use_id.to_owned(),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// The issues of a valid request with the fields of `changes` replaced.
+    fn issues(changes: Value) -> Vec<ValidationIssue> {
+        let mut body = json!({
+            "model": "claude-sonnet-4-5",
+            "max_tokens": 2048,
+            "messages": [{"role": "user", "content": "Hello"}],
+        });
+        for (field, value) in changes.as_object().unwrap() {
+            body[field] = value.clone();
+        }
+        validate(&body)
+    }
+
+    fn tool_use(id: &str) -> Value {
+        json!({"role": "assistant", "content": [
+            {"type": "tool_use", "id": id, "name": "get_weather", "input": {}}
+        ]})
+    }
+
+    fn tool_result(id: &str) -> Value {
+        json!({"role": "user", "content": [
+            {"type": "tool_result", "tool_use_id": id, "content": "Sunny"}
+        ]})
+    }
+
+    #[test]
+    fn valid_request_has_no_issues() {
+        assert_eq!(issues(json!({})), vec![]);
+    }
+
+    #[test]
+    fn empty_model() {
+        assert_eq!(
+            issues(json!({"model": ""})),
+            vec![ValidationIssue::EmptyModel]
+        );
+        assert_eq!(
+            issues(json!({"model": "  "})),
+            vec![ValidationIssue::EmptyModel]
+        );
+    }
+
+    #[test]
+    fn zero_max_tokens() {
+        assert_eq!(
+            issues(json!({"max_tokens": 0})),
+            vec![ValidationIssue::ZeroMaxTokens]
+        );
+    }
+
+    #[test]
+    fn max_tokens_above_the_model_limit() {
+        assert_eq!(
+            issues(json!({"model": "claude-3-opus-latest", "max_tokens": 5000})),
+            vec![ValidationIssue::MaxTokensAboveLimit {
+                model: "claude-3-opus-latest".to_owned(),
+                max_tokens: 5000,
+                limit: 4096,
+            }]
+        );
+        assert_eq!(
+            issues(json!({"model": "claude-future-9", "max_tokens": 1_000_000})),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn temperature_out_of_range() {
+        assert_eq!(
+            issues(json!({"temperature": 1.5})),
+            vec![ValidationIssue::TemperatureOutOfRange(1.5)]
+        );
+        assert_eq!(
+            issues(json!({"temperature": -0.1})),
+            vec![ValidationIssue::TemperatureOutOfRange(-0.1)]
+        );
+        assert_eq!(issues(json!({"temperature": 1.0})), vec![]);
+    }
+
+    #[test]
+    fn top_p_out_of_range() {
+        assert_eq!(
+            issues(json!({"top_p": 1.1})),
+            vec![ValidationIssue::TopPOutOfRange(1.1)]
+        );
+        assert_eq!(issues(json!({"top_p": 0.0})), vec![]);
+    }
+
+    #[test]
+    fn top_k_with_top_p() {
+        assert_eq!(
+            issues(json!({"top_k": 10, "top_p": 0.9})),
+            vec![ValidationIssue::TopKWithTopP {
+                model: "claude-sonnet-4-5".to_owned()
+            }]
+        );
+        assert_eq!(
+            issues(json!({"model": "claude-3-7-sonnet-latest", "top_k": 10, "top_p": 0.9})),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn thinking_unsupported() {
+        assert_eq!(
+            issues(json!({
+                "model": "claude-3-5-haiku-latest",
+                "thinking": {"type": "enabled", "budget_tokens": 1024},
+            })),
+            vec![ValidationIssue::ThinkingUnsupported {
+                model: "claude-3-5-haiku-latest".to_owned()
+            }]
+        );
+    }
+
+    #[test]
+    fn thinking_budget() {
+        for budget_tokens in [1023, 2048, 4096] {
+            assert_eq!(
+                issues(json!({"thinking": {"type": "enabled", "budget_tokens": budget_tokens}})),
+                vec![ValidationIssue::ThinkingBudget {
+                    budget_tokens,
+                    max_tokens: 2048,
+                }]
+            );
+        }
+        assert_eq!(
+            issues(json!({"thinking": {"type": "enabled", "budget_tokens": 1024}})),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn empty_messages() {
+        assert_eq!(
+            issues(json!({"messages": []})),
+            vec![ValidationIssue::EmptyMessages]
+        );
+    }
+
+    #[test]
+    fn assistant_first() {
+        assert_eq!(
+            issues(json!({"messages": [
+                {"role": "assistant", "content": "Hi"},
+                {"role": "user", "content": "Hello"},
+            ]})),
+            vec![ValidationIssue::AssistantFirst]
+        );
+    }
+
+    #[test]
+    fn consecutive_same_role() {
+        assert_eq!(
+            issues(json!({"messages": [
+                {"role": "user", "content": "Hello"},
+                {"role": "assistant", "content": "Hi"},
+                {"role": "assistant", "content": "Anyone there?"},
+            ]})),
+            vec![ValidationIssue::ConsecutiveRole {
+                index: 2,
+                role: "assistant".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn unknown_tool_use_id() {
+        let user = json!({"role": "user", "content": "What's the weather?"});
+        assert_eq!(
+            issues(json!({"messages": [user, tool_use("toolu_1"), tool_result("toolu_2")]})),
+            vec![ValidationIssue::UnknownToolUseId {
+                index: 2,
+                tool_use_id: "toolu_2".to_owned(),
+            }]
+        );
+        assert_eq!(
+            issues(json!({"messages": [user, tool_use("toolu_1"), tool_result("toolu_1")]})),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn error_lists_every_issue() {
+        let error = ValidationError {
+            issues: issues(json!({"model": "", "max_tokens": 0})),
+        };
+
+        assert_eq!(
+            error.to_string(),
+            "Invalid request: model is empty; max_tokens must be greater than 0"
+        );
+    }
+}
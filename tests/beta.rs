@@ -0,0 +1,89 @@
+use anthropic_sdk::{Beta, Client, MockResponse, MockTransport};
+use serde_json::json;
+
+fn client() -> Client {
+    Client::new()
+        .auth("test-key")
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+}
+
+async fn beta_header(client: Client) -> Option<String> {
+    let transport = MockTransport::new().reply(MockResponse::message("Hi"));
+    client
+        .transport(transport.clone())
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+    let request = transport.requests().remove(0);
+    request
+        .headers
+        .get("anthropic-beta")
+        .map(|value| value.to_str().unwrap().to_owned())
+}
+
+#[tokio::test]
+async fn betas_are_joined_without_duplicates() {
+    assert_eq!(beta_header(client()).await, None);
+
+    let header = beta_header(
+        client()
+            .beta(Beta::TokenEfficientTools)
+            .beta("token-efficient-tools-2025-02-19")
+            .betas([
+                "some-new-beta-2026-01-01",
+                "interleaved-thinking-2025-05-14",
+            ]),
+    )
+    .await;
+    assert_eq!(
+        header.as_deref(),
+        Some("token-efficient-tools-2025-02-19,interleaved-thinking-2025-05-14,some-new-beta-2026-01-01")
+    );
+    assert_eq!(
+        Beta::from("some-new-beta-2026-01-01"),
+        Beta::Other("some-new-beta-2026-01-01".to_owned())
+    );
+}
+
+#[tokio::test]
+async fn features_add_the_betas_they_need() {
+    let header = beta_header(
+        client()
+            .messages(&json!([{"role": "user", "content": [
+                {"type": "document", "source": {"type": "file", "file_id": "file_1"}},
+                {"type": "text", "text": "Summarize", "cache_control": {"type": "ephemeral", "ttl": "1h"}},
+            ]}]))
+            .context_management(&json!({"edits": [{"type": "clear_tool_uses_20250919"}]})),
+    )
+    .await;
+
+    assert_eq!(
+        header.as_deref(),
+        Some("files-api-2025-04-14,context-management-2025-06-27,extended-cache-ttl-2025-04-11")
+    );
+}
+
+#[tokio::test]
+async fn token_efficient_tools_adds_its_beta() {
+    let tools = json!([{"name": "get_weather", "input_schema": {"type": "object"}}]);
+
+    assert_eq!(
+        beta_header(client().tools(&tools).token_efficient_tools(true))
+            .await
+            .as_deref(),
+        Some("token-efficient-tools-2025-02-19")
+    );
+    assert_eq!(
+        beta_header(
+            client()
+                .tools(&tools)
+                .token_efficient_tools(true)
+                .token_efficient_tools(false)
+        )
+        .await,
+        None
+    );
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anthropic_sdk::{CancellationToken, Cancelled, Client};
+use common::{serve, sse_response, text_events};
+use serde_json::json;
+
+#[tokio::test]
+async fn cancelling_a_stream_returns_partial_output() {
+    let events = text_events(&["Once ", "upon"]);
+    let (base_url, _) = serve(vec![sse_response(
+        &events
+            .iter()
+            .map(|event| (Duration::ZERO, event.as_str()))
+            .collect::<Vec<_>>(),
+        true,
+    )])
+    .await;
+
+    let token = CancellationToken::new();
+    let received = Arc::new(Mutex::new(String::new()));
+    let request = Client::new()
+        .auth("test-key")
+        .base_url(&base_url)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Tell me a story"}]))
+        .stream(true)
+        .build()
+        .unwrap()
+        .cancellation_token(token.clone());
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        request.execute(|text| {
+            let received = received.clone();
+            let token = token.clone();
+            async move {
+                let mut received = received.lock().unwrap();
+                received.push_str(&text);
+                if received.as_str() == "Once upon" {
+                    token.cancel();
+                }
+            }
+        }),
+    )
+    .await
+    .expect("cancellation should end the stream promptly");
+
+    let error = result.unwrap_err();
+    let cancelled = error.downcast_ref::<Cancelled>().unwrap();
+    assert_eq!(cancelled.partial.text, "Once upon");
+    assert_eq!(cancelled.partial.usage.input_tokens, 5);
+}
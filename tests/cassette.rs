@@ -0,0 +1,142 @@
+mod common;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anthropic_sdk::{Cassette, Client, Timeout, TimeoutPhase};
+use common::{http_response, serve, sse_response, stop_events, text_events, unreachable, MESSAGE};
+use serde_json::json;
+
+fn cassette_path(name: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("anthropic-sdk-{}-{name}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+fn client(base_url: &str) -> Client {
+    Client::new()
+        .auth("sk-ant-secret")
+        .base_url(base_url)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .max_retries(1)
+}
+
+#[tokio::test]
+async fn replays_recorded_streams_with_their_timing() {
+    let path = cassette_path("stream");
+    let events = text_events(&["Hello", " world"]);
+    let stop = stop_events(2);
+    let (base_url, served) = serve(vec![sse_response(
+        &[
+            (Duration::ZERO, events[0].as_str()),
+            (Duration::from_millis(150), events[1].as_str()),
+            (Duration::ZERO, events[2].as_str()),
+            (Duration::ZERO, stop[0].as_str()),
+            (Duration::ZERO, stop[1].as_str()),
+        ],
+        false,
+    )])
+    .await;
+
+    let recorded = client(&base_url)
+        .stream(true)
+        .transport(Cassette::record(&path))
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap();
+    assert_eq!(recorded.text, "Hello world");
+
+    let cassette = std::fs::read_to_string(&path).unwrap();
+    assert!(!cassette.contains("sk-ant-secret"));
+    assert!(cassette.contains("[REDACTED]"));
+
+    let replayed = client("http://127.0.0.1:9")
+        .stream(true)
+        .transport(Cassette::replay(&path).unwrap())
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap();
+    assert_eq!(replayed.text, "Hello world");
+    assert_eq!(replayed.usage.output_tokens, 2);
+    assert!(replayed.metrics.unwrap().time_to_first_token.unwrap() >= Duration::from_millis(150));
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn replays_interactions_in_order_and_rejects_unknown_requests() {
+    let path = cassette_path("retry");
+    let (base_url, _) = serve(vec![
+        http_response(
+            "529 Site Overloaded",
+            &[("retry-after", "0")],
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+        ),
+        http_response("200 OK", &[("content-type", "application/json")], MESSAGE),
+    ])
+    .await;
+
+    client(&base_url)
+        .transport(Cassette::record(&path))
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+
+    let cassette = Cassette::replay(&path).unwrap().timing(false);
+    let response = client("http://127.0.0.1:9")
+        .transport(cassette.clone())
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+    assert_eq!(response.id, "msg_1");
+    assert_eq!(response.usage.output_tokens, 1);
+
+    let error = client("http://127.0.0.1:9")
+        .messages(&json!([{"role": "user", "content": "Something else"}]))
+        .transport(cassette)
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("No interaction in cassette"));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn records_through_the_clients_http_client() {
+    let path = cassette_path("connect");
+    let server = unreachable();
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        client(&server.base_url)
+            .max_retries(0)
+            .connect_timeout(Duration::from_millis(200))
+            .transport(Cassette::record(&path))
+            .build()
+            .unwrap()
+            .execute_and_return_json(),
+    )
+    .await
+    .expect("the client's connect timeout should apply while recording");
+
+    let error = result.unwrap_err();
+    assert_eq!(
+        error.downcast_ref::<Timeout>().unwrap().phase,
+        TimeoutPhase::Connect
+    );
+    assert!(!path.exists());
+}
@@ -0,0 +1,194 @@
+//! A raw TCP server for the integration tests that need real sockets.
+//!
+//! Tests that don't need a socket use `MockTransport`, and the end-to-end tests
+//! of `MockServer` itself live in `tests/mock_server.rs`. The scenarios here
+//! stay on raw TCP because `MockServer` needs the `mock-server` feature, which
+//! a plain `cargo test` doesn't enable, and because it can't script them:
+//!
+//! - connections left open after partial output, for cancellation and idle
+//!   timeouts, and listeners that never accept, for connect timeouts;
+//! - non-streamed bodies written slowly in pieces;
+//! - the exact bytes of each event, such as `ping`s and the model and usage
+//!   a response reports;
+//! - arbitrary response headers, such as `request-id` and rate limits;
+//! - the raw text of each request, headers included.
+#![allow(dead_code)]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpSocket};
+
+pub const MESSAGE: &str = r#"{"id":"msg_1","model":"claude-3-opus-20240229","stop_reason":"end_turn","role":"assistant","content":[{"type":"text","text":"Hi"}],"usage":{"input_tokens":5,"output_tokens":1}}"#;
+
+/// One scripted reply: the status line and headers, then body chunks written
+/// after the given delays. A reply that hangs keeps the connection open.
+pub struct Reply {
+    head_delay: Duration,
+    head: String,
+    chunks: Vec<(Duration, String)>,
+    hang: bool,
+}
+
+impl Reply {
+    /// Waits `delay` before writing the status line.
+    pub fn delayed(mut self, delay: Duration) -> Self {
+        self.head_delay = delay;
+        self
+    }
+
+    /// Writes the body in `pieces` parts, `delay` apart.
+    pub fn trickle(mut self, pieces: usize, delay: Duration) -> Self {
+        let body: String = self.chunks.drain(..).map(|(_, chunk)| chunk).collect();
+        let size = body.len().div_ceil(pieces);
+        self.chunks = body
+            .as_bytes()
+            .chunks(size)
+            .map(|piece| (delay, String::from_utf8_lossy(piece).into_owned()))
+            .collect();
+        self
+    }
+}
+
+pub fn http_response(status: &str, headers: &[(&str, &str)], body: &str) -> Reply {
+    let mut head = format!("HTTP/1.1 {status}\r\nconnection: close\r\n");
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str(&format!("content-length: {}\r\n\r\n", body.len()));
+    Reply {
+        head_delay: Duration::ZERO,
+        head,
+        chunks: vec![(Duration::ZERO, body.to_string())],
+        hang: false,
+    }
+}
+
+pub fn sse_response(events: &[(Duration, &str)], hang: bool) -> Reply {
+    Reply {
+        head_delay: Duration::ZERO,
+        head: "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-type: text/event-stream\r\n\r\n"
+            .to_string(),
+        chunks: events
+            .iter()
+            .map(|(delay, event)| (*delay, event.to_string()))
+            .collect(),
+        hang,
+    }
+}
+
+pub fn text_events(texts: &[&str]) -> Vec<String> {
+    let mut events = vec![
+        "event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\",\"type\":\"message\",\"role\":\"assistant\",\"model\":\"claude-3-opus-20240229\",\"usage\":{\"input_tokens\":5,\"output_tokens\":1}}}\n\n".to_string(),
+    ];
+    for text in texts {
+        events.push(format!(
+            "event: content_block_delta\ndata: {{\"type\":\"content_block_delta\",\"index\":0,\"delta\":{{\"type\":\"text_delta\",\"text\":\"{text}\"}}}}\n\n"
+        ));
+    }
+    events
+}
+
+pub fn stop_events(output_tokens: u32) -> Vec<String> {
+    vec![
+        format!("event: message_delta\ndata: {{\"type\":\"message_delta\",\"delta\":{{\"stop_reason\":\"end_turn\",\"stop_sequence\":null}},\"usage\":{{\"output_tokens\":{output_tokens}}}}}\n\n"),
+        "event: message_stop\ndata: {\"type\":\"message_stop\"}\n\n".to_string(),
+    ]
+}
+
+/// Serves one scripted reply per connection, in order, and counts requests.
+pub async fn serve(replies: Vec<Reply>) -> (String, Arc<AtomicUsize>) {
+    let (base_url, count, _) = serve_recording(replies).await;
+    (base_url, count)
+}
+
+/// Like [`serve`], also keeping the text of every request received.
+pub async fn serve_recording(
+    replies: Vec<Reply>,
+) -> (String, Arc<AtomicUsize>, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base_url = format!("http://{}", listener.local_addr().unwrap());
+    let count = Arc::new(AtomicUsize::new(0));
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let served = count.clone();
+    let received = requests.clone();
+
+    tokio::spawn(async move {
+        for reply in replies {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let request = read_request(&mut socket).await;
+            received.lock().unwrap().push(request);
+            served.fetch_add(1, Ordering::SeqCst);
+
+            tokio::spawn(async move {
+                tokio::time::sleep(reply.head_delay).await;
+                if socket.write_all(reply.head.as_bytes()).await.is_err() {
+                    return;
+                }
+                for (delay, chunk) in reply.chunks {
+                    tokio::time::sleep(delay).await;
+                    if socket.write_all(chunk.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+                if reply.hang {
+                    let mut buffer = [0u8; 1];
+                    let _ = socket.read(&mut buffer).await;
+                }
+                let _ = socket.shutdown().await;
+            });
+        }
+    });
+
+    (base_url, count, requests)
+}
+
+/// A listener whose backlog is already full, so new connections hang in the
+/// handshake. Keep it alive for as long as the test needs the address.
+pub struct Unreachable {
+    pub base_url: String,
+    _listener: TcpListener,
+    _connections: Vec<std::net::TcpStream>,
+}
+
+pub fn unreachable() -> Unreachable {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let listener = socket.listen(1).unwrap();
+    let address = listener.local_addr().unwrap();
+    let connections = (0..64)
+        .map_while(|_| {
+            std::net::TcpStream::connect_timeout(&address, Duration::from_millis(50)).ok()
+        })
+        .collect();
+    Unreachable {
+        base_url: format!("http://{address}"),
+        _listener: listener,
+        _connections: connections,
+    }
+}
+
+async fn read_request(socket: &mut tokio::net::TcpStream) -> String {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let read = socket.read(&mut buffer).await.unwrap();
+        if read == 0 {
+            return String::from_utf8_lossy(&request).into_owned();
+        }
+        request.extend_from_slice(&buffer[..read]);
+        let text = String::from_utf8_lossy(&request);
+        if let Some(end) = text.find("\r\n\r\n") {
+            let length = text[..end]
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .and_then(|value| value.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            if request.len() >= end + 4 + length {
+                return text.into_owned();
+            }
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+use anthropic_sdk::{Client, MockResponse, MockTransport};
+use serde_json::json;
+
+const CONFIG: &str = r#"
+default_profile = "dev"
+
+[profiles.dev]
+api_key = "sk-ant-dev"
+model = "claude-3-haiku-20240307"
+max_tokens = 512
+
+[profiles.prod]
+api_key_env = "ANTHROPIC_SDK_TEST_PROD_KEY"
+base_url = "https://gateway.example.com"
+beta = "prompt-caching-2024-07-31"
+max_retries = 5
+timeout = 90
+"#;
+
+/// Serializes the tests that touch the environment.
+static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+/// Sets environment variables for one test and restores their previous values
+/// when dropped.
+struct EnvGuard {
+    saved: Vec<(&'static str, Option<String>)>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl EnvGuard {
+    fn new() -> Self {
+        Self {
+            saved: Vec::new(),
+            _lock: ENV_LOCK
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        }
+    }
+
+    fn set(&mut self, name: &'static str, value: impl AsRef<std::ffi::OsStr>) {
+        if !self.saved.iter().any(|(saved, _)| *saved == name) {
+            self.saved.push((name, std::env::var(name).ok()));
+        }
+        std::env::set_var(name, value);
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        for (name, value) in self.saved.drain(..).rev() {
+            match value {
+                Some(value) => std::env::set_var(name, value),
+                None => std::env::remove_var(name),
+            }
+        }
+    }
+}
+
+fn config_file(name: &str, contents: &str) -> PathBuf {
+    let path =
+        std::env::temp_dir().join(format!("anthropic-sdk-{}-{name}.toml", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+async fn sent_request(client: Client) -> anthropic_sdk::HttpRequest {
+    let transport = MockTransport::new().reply(MockResponse::message("Hi"));
+    client
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .transport(transport.clone())
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+    transport.requests().remove(0)
+}
+
+#[tokio::test]
+async fn profiles_set_key_and_defaults() {
+    let mut env = EnvGuard::new();
+    let path = config_file("profiles", CONFIG);
+
+    let dev = sent_request(Client::from_config(&path, "dev").unwrap()).await;
+    assert_eq!(dev.headers["x-api-key"], "sk-ant-dev");
+    assert_eq!(dev.body["model"], "claude-3-haiku-20240307");
+    assert_eq!(dev.body["max_tokens"], 512);
+
+    env.set("ANTHROPIC_SDK_TEST_PROD_KEY", "sk-ant-prod");
+    let prod = Client::from_config(&path, "prod")
+        .unwrap()
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .build()
+        .unwrap();
+    let curl = prod.to_curl();
+    assert!(curl.contains("https://gateway.example.com/v1/messages"));
+    assert!(curl.contains("anthropic-beta: prompt-caching-2024-07-31"));
+
+    let missing = Client::from_config(&path, "staging").unwrap_err();
+    assert!(missing.to_string().contains("No profile staging"));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn rejects_unknown_settings() {
+    let path = config_file("unknown", "[profiles.dev]\napi_kee = \"typo\"\n");
+
+    let error = Client::from_config(&path, "dev").unwrap_err();
+    assert!(format!("{error:#}").contains("unknown field `api_kee`"));
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn from_env_reads_variables_over_the_profile() {
+    let mut env = EnvGuard::new();
+    let path = config_file("env", CONFIG);
+    env.set("ANTHROPIC_CONFIG", &path);
+    env.set("ANTHROPIC_PROFILE", "dev");
+    env.set("ANTHROPIC_API_KEY", "sk-ant-env");
+    env.set("ANTHROPIC_VERSION", "2024-01-01");
+
+    let request = sent_request(Client::from_env().unwrap()).await;
+    assert_eq!(request.headers["x-api-key"], "sk-ant-env");
+    assert_eq!(request.headers["anthropic-version"], "2024-01-01");
+    assert_eq!(request.body["model"], "claude-3-haiku-20240307");
+
+    env.set("ANTHROPIC_MAX_RETRIES", "many");
+    let error = Client::from_env().unwrap_err();
+    assert!(error.to_string().contains("Invalid ANTHROPIC_MAX_RETRIES"));
+
+    let _ = std::fs::remove_file(path);
+}
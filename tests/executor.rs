@@ -0,0 +1,148 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anthropic_sdk::{
+    run_many, run_many_with, Client, MockResponse, MockTransport, Progress, Request, RunOptions,
+    Transport,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde_json::json;
+
+fn request<T: Transport + 'static>(transport: T) -> Request {
+    Client::new()
+        .auth("test-key")
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .transport(transport)
+        .build()
+        .unwrap()
+}
+
+/// A message reporting 5 input and 1 output tokens, read `delay` after the headers.
+fn message(delay: Duration) -> MockResponse {
+    MockResponse::json(
+        200,
+        &json!({
+            "id": "msg_1",
+            "model": "claude-3-opus-20240229",
+            "stop_reason": "end_turn",
+            "role": "assistant",
+            "content": [{"type": "text", "text": "Hi"}],
+            "usage": {"input_tokens": 5, "output_tokens": 1},
+        }),
+    )
+    .delay(delay)
+}
+
+/// Slowest first, so that finishing order is the reverse of input order.
+fn slowest_first(count: u64) -> Vec<Request> {
+    (0..count)
+        .map(|index| {
+            let delay = Duration::from_millis(40 * (count - index));
+            request(MockTransport::new().reply(message(delay)))
+        })
+        .collect()
+}
+
+/// Tracks how many requests are in flight at once.
+#[derive(Clone, Default)]
+struct InFlight {
+    current: Arc<AtomicUsize>,
+    max: Arc<AtomicUsize>,
+}
+
+#[async_trait]
+impl Transport for InFlight {
+    async fn send(&self, request: reqwest::Request) -> Result<reqwest::Response> {
+        let current = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max.fetch_max(current, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        self.current.fetch_sub(1, Ordering::SeqCst);
+
+        MockTransport::new()
+            .reply(message(Duration::ZERO))
+            .send(request)
+            .await
+    }
+}
+
+#[tokio::test]
+async fn yields_results_as_they_finish() {
+    let indices: Vec<usize> = run_many(slowest_first(4), 4)
+        .map(|(index, result)| {
+            result.unwrap();
+            index
+        })
+        .collect()
+        .await;
+
+    assert_eq!(indices, [3, 2, 1, 0]);
+}
+
+#[tokio::test]
+async fn preserve_order_yields_results_in_input_order() {
+    let options = RunOptions::new(4).preserve_order(true);
+    let indices: Vec<usize> = run_many_with(slowest_first(4), options)
+        .map(|(index, result)| {
+            result.unwrap();
+            index
+        })
+        .collect()
+        .await;
+
+    assert_eq!(indices, [0, 1, 2, 3]);
+}
+
+#[tokio::test]
+async fn respects_the_concurrency_limit() {
+    let in_flight = InFlight::default();
+    let requests: Vec<Request> = (0..8).map(|_| request(in_flight.clone())).collect();
+
+    let results: Vec<_> = run_many(requests, 3).collect().await;
+
+    assert_eq!(results.len(), 8);
+    assert!(results.iter().all(|(_, result)| result.is_ok()));
+    assert_eq!(in_flight.max.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn reports_progress_once_per_request_including_failures() {
+    let mut requests = slowest_first(3);
+    requests.insert(
+        1,
+        request(MockTransport::new().reply(MockResponse::error(
+            400,
+            "invalid_request_error",
+            "Bad request",
+        ))),
+    );
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let recorded = reports.clone();
+    let options = RunOptions::new(2).on_progress(move |progress| {
+        recorded.lock().unwrap().push(*progress);
+    });
+
+    let results: Vec<_> = run_many_with(requests, options).collect().await;
+
+    assert_eq!(
+        results.iter().filter(|(_, result)| result.is_err()).count(),
+        1
+    );
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 4);
+    assert_eq!(
+        reports.last(),
+        Some(&Progress {
+            completed: 3,
+            failed: 1,
+            input_tokens: 15,
+            output_tokens: 3,
+        })
+    );
+    for (n, progress) in reports.iter().enumerate() {
+        assert_eq!(progress.completed + progress.failed, n + 1);
+    }
+}
@@ -0,0 +1,187 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anthropic_sdk::{Client, ContentItem, ValidationError, ValidationIssue};
+use common::{http_response, serve, sse_response, stop_events, text_events, MESSAGE};
+use serde_json::json;
+
+const HAIKU_MESSAGE: &str = r#"{"id":"msg_1","model":"claude-haiku-4-5-20251001","stop_reason":"end_turn","role":"assistant","content":[{"type":"text","text":"Hi"}],"usage":{"input_tokens":5,"output_tokens":1}}"#;
+
+fn client(base_url: &str) -> Client {
+    Client::new()
+        .auth("test-key")
+        .base_url(base_url)
+        .model("claude-sonnet-4-5")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .max_tokens(2048)
+        .max_retries(0)
+}
+
+#[tokio::test]
+async fn falls_back_to_next_model_on_overload() {
+    let overloaded =
+        r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+    let (base_url, count) = serve(vec![
+        http_response("529 Overloaded", &[], overloaded),
+        http_response("500 Internal Server Error", &[], "{}"),
+        http_response(
+            "200 OK",
+            &[("content-type", "application/json")],
+            HAIKU_MESSAGE,
+        ),
+    ])
+    .await;
+
+    let response = client(&base_url)
+        .fallback_models(["claude-opus-4-1", "claude-haiku-4-5"])
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+    assert_eq!(response.model(), "claude-haiku-4-5-20251001");
+    assert_eq!(response.requested_model(), "claude-haiku-4-5");
+}
+
+#[tokio::test]
+async fn falls_back_when_a_model_stalls() {
+    let ok = || http_response("200 OK", &[("content-type", "application/json")], MESSAGE);
+    let (base_url, count) = serve(vec![
+        ok().delayed(Duration::from_secs(2)),
+        ok().trickle(2, Duration::from_secs(2)),
+        ok(),
+    ])
+    .await;
+
+    let response = client(&base_url)
+        .first_byte_timeout(Duration::from_millis(200))
+        .idle_timeout(Duration::from_millis(200))
+        .fallback_models(["claude-opus-4-1", "claude-haiku-4-5"])
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap();
+
+    assert_eq!(count.load(Ordering::SeqCst), 3);
+    assert_eq!(response.requested_model(), "claude-haiku-4-5");
+    assert_eq!(response.model(), "claude-3-opus-20240229");
+}
+
+#[tokio::test]
+async fn falls_back_when_a_stream_stalls_before_its_first_event() {
+    let mut events = text_events(&["Hi"]);
+    events.extend(stop_events(1));
+    let (base_url, count) = serve(vec![
+        sse_response(&[], true),
+        sse_response(
+            &events
+                .iter()
+                .map(|event| (Duration::ZERO, event.as_str()))
+                .collect::<Vec<_>>(),
+            false,
+        ),
+    ])
+    .await;
+
+    let response = client(&base_url)
+        .stream(true)
+        .idle_timeout(Duration::from_millis(200))
+        .fallback_models(["claude-haiku-4-5"])
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap();
+
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    assert_eq!(response.text, "Hi");
+    assert_eq!(response.requested_model(), "claude-haiku-4-5");
+    assert_eq!(response.recovered, 0);
+}
+
+#[tokio::test]
+async fn client_errors_do_not_fall_back() {
+    let (base_url, count) = serve(vec![http_response("400 Bad Request", &[], "{}")]).await;
+
+    let result = client(&base_url)
+        .fallback_models(["claude-haiku-4-5"])
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await;
+
+    assert!(result.is_err());
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn fallbacks_without_thinking_support_fail_validation() {
+    let error = client("http://localhost")
+        .thinking(1024)
+        .fallback_models(["claude-3-5-haiku-20241022"])
+        .build()
+        .err()
+        .unwrap();
+
+    let error = error.downcast_ref::<ValidationError>().unwrap();
+    assert_eq!(
+        error.issues,
+        vec![ValidationIssue::ThinkingUnsupported {
+            model: "claude-3-5-haiku-20241022".to_string()
+        }]
+    );
+}
+
+const THINKING_MESSAGE: &str = r#"{"id":"msg_1","model":"claude-sonnet-4-5","stop_reason":"end_turn","role":"assistant","content":[{"type":"thinking","thinking":"Say hi.","signature":"sig"},{"type":"redacted_thinking","data":"opaque"},{"type":"text","text":"Hi"}],"usage":{"input_tokens":5,"output_tokens":12}}"#;
+
+#[tokio::test]
+async fn non_streaming_responses_with_thinking_blocks_parse() {
+    let (base_url, _) = serve(vec![
+        http_response(
+            "200 OK",
+            &[("content-type", "application/json")],
+            THINKING_MESSAGE,
+        ),
+        http_response(
+            "200 OK",
+            &[("content-type", "application/json")],
+            THINKING_MESSAGE,
+        ),
+    ])
+    .await;
+
+    let response = client(&base_url)
+        .thinking(1024)
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+    assert!(matches!(
+        &response.content[..],
+        [
+            ContentItem::Thinking { thinking, signature },
+            ContentItem::RedactedThinking { data },
+            ContentItem::Text { text },
+        ] if thinking == "Say hi." && signature == "sig" && data == "opaque" && text == "Hi"
+    ));
+
+    let mut texts = Vec::new();
+    let response = client(&base_url)
+        .thinking(1024)
+        .build()
+        .unwrap()
+        .execute(|text| {
+            texts.push(text);
+            async {}
+        })
+        .await
+        .unwrap();
+    assert_eq!(texts, ["Hi"]);
+    assert_eq!(response.text, "Hi");
+}
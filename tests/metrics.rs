@@ -0,0 +1,56 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anthropic_sdk::Client;
+use common::{serve, sse_response, stop_events, text_events};
+use serde_json::json;
+
+#[tokio::test]
+async fn measures_streaming_latency() {
+    let events = text_events(&["One", " two", " three"]);
+    let stop = stop_events(30);
+    let millis = Duration::from_millis;
+    let (base_url, _) = serve(vec![sse_response(
+        &[
+            (Duration::ZERO, events[0].as_str()),
+            (millis(100), events[1].as_str()),
+            (millis(40), events[2].as_str()),
+            (millis(40), events[3].as_str()),
+            (Duration::ZERO, stop[0].as_str()),
+            (Duration::ZERO, stop[1].as_str()),
+        ],
+        false,
+    )])
+    .await;
+
+    let exported = Arc::new(Mutex::new(None));
+    let response = Client::new()
+        .auth("test-key")
+        .base_url(&base_url)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Count"}]))
+        .stream(true)
+        .on_stream_metrics({
+            let exported = exported.clone();
+            move |metrics| *exported.lock().unwrap() = Some(*metrics)
+        })
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap();
+
+    let metrics = response.metrics.unwrap();
+    assert_eq!(*exported.lock().unwrap(), Some(metrics));
+    assert_eq!(metrics.text_deltas, 3);
+    assert!(metrics.time_to_first_byte < metrics.time_to_first_token.unwrap());
+    assert!(metrics.time_to_first_token.unwrap() >= millis(100));
+
+    let inter_token = metrics.inter_token_latency.unwrap();
+    assert!(inter_token.p50 >= millis(30));
+    assert_eq!(inter_token.p99, inter_token.max);
+    assert!(metrics.output_tokens_per_second.unwrap() > 0.0);
+    assert!(metrics.total >= metrics.time_to_first_token.unwrap());
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+use anthropic_sdk::{Client, HttpRequest, Middleware, MockTransport};
+use anyhow::Result;
+use async_trait::async_trait;
+use common::{http_response, serve_recording, MESSAGE};
+use serde_json::json;
+
+struct Tenant(&'static str);
+
+#[async_trait]
+impl Middleware for Tenant {
+    async fn before_send(&self, request: &mut HttpRequest) -> Result<()> {
+        request
+            .headers
+            .insert("x-tenant", self.0.parse().unwrap());
+        if request.url.path() == "/v1/messages" {
+            request.body["metadata"] = json!({"user_id": self.0});
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Statuses(Arc<Mutex<Vec<u16>>>);
+
+#[async_trait]
+impl Middleware for Statuses {
+    async fn after_receive(&self, response: &reqwest::Response) -> Result<()> {
+        self.0.lock().unwrap().push(response.status().as_u16());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn middleware_runs_for_messages_and_count_tokens() {
+    let (base_url, _, requests) = serve_recording(vec![
+        http_response(
+            "200 OK",
+            &[("content-type", "application/json")],
+            r#"{"input_tokens":7}"#,
+        ),
+        http_response("200 OK", &[("content-type", "application/json")], MESSAGE),
+    ])
+    .await;
+
+    let statuses = Statuses::default();
+    let seen = statuses.0.clone();
+    let request = Client::new()
+        .auth("test-key")
+        .base_url(&base_url)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .middleware(Tenant("acme"))
+        .middleware(statuses)
+        .build()
+        .unwrap();
+
+    assert_eq!(request.count_tokens().await.unwrap(), 7);
+    request.execute_and_return_json().await.unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert!(requests[0].starts_with("POST /v1/messages/count_tokens"));
+    assert!(requests.iter().all(|request| request.contains("x-tenant: acme")));
+    assert!(requests[1].contains(r#""metadata":{"user_id":"acme"}"#));
+    assert_eq!(*seen.lock().unwrap(), vec![200, 200]);
+}
+
+#[tokio::test]
+async fn builder_bypasses_middleware_and_transport() {
+    let (base_url, count, requests) = serve_recording(vec![http_response(
+        "200 OK",
+        &[("content-type", "application/json")],
+        MESSAGE,
+    )])
+    .await;
+
+    let transport = MockTransport::new();
+    let statuses = Statuses::default();
+    let seen = statuses.0.clone();
+    let builder = Client::new()
+        .auth("test-key")
+        .base_url(&base_url)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .middleware(Tenant("acme"))
+        .middleware(statuses)
+        .transport(transport.clone())
+        .builder()
+        .unwrap();
+    let response = builder.send().await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    assert!(transport.requests().is_empty());
+    assert!(seen.lock().unwrap().is_empty());
+    let request = &requests.lock().unwrap()[0];
+    assert!(!request.contains("x-tenant"));
+    assert!(!request.contains("metadata"));
+}
@@ -0,0 +1,150 @@
+#![cfg(feature = "mock-server")]
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anthropic_sdk::{Client, ContentItem, MockServer, ScriptedReply, StreamError};
+use serde_json::{json, Value};
+
+fn client(server: &MockServer) -> Client {
+    Client::new()
+        .auth("test-key")
+        .base_url(server.url())
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .max_retries(2)
+}
+
+#[tokio::test]
+async fn retries_then_parses_split_stream() {
+    let server = MockServer::start().await.unwrap();
+    server.reply(ScriptedReply::rate_limited(0));
+    server.reply(
+        ScriptedReply::text("Hello there world")
+            .chunk_size(7)
+            .chunk_delay(Duration::from_millis(1)),
+    );
+
+    let received = Arc::new(Mutex::new(String::new()));
+    let response = client(&server)
+        .stream(true)
+        .build()
+        .unwrap()
+        .execute(|text| {
+            let received = received.clone();
+            async move { received.lock().unwrap().push_str(&text) }
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(response.text, "Hello there world");
+    assert_eq!(*received.lock().unwrap(), "Hello there world");
+    assert!(response.request_id().unwrap().starts_with("req_mock"));
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(server.requests()[1].body["stream"], true);
+}
+
+#[tokio::test]
+async fn surfaces_mid_stream_errors() {
+    let server = MockServer::start().await.unwrap();
+    server.reply(ScriptedReply::text("One two three").fail_stream_after(
+        1,
+        "overloaded_error",
+        "Overloaded",
+    ));
+
+    let error = client(&server)
+        .stream(true)
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap_err();
+
+    let stream_error = error.downcast_ref::<StreamError>().unwrap();
+    assert_eq!(stream_error.error_type, "overloaded_error");
+}
+
+#[tokio::test]
+async fn serves_tool_calls_token_counts_models_and_batches() {
+    let server = MockServer::start().await.unwrap();
+    server.reply(ScriptedReply::tool_use(
+        "get_weather",
+        json!({"location": "Paris"}),
+    ));
+
+    let response = client(&server)
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+    assert_eq!(response.stop_reason, "tool_use");
+    match &response.content[0] {
+        ContentItem::ToolUse { name, input, .. } => {
+            assert_eq!(name, "get_weather");
+            assert_eq!(input["location"], "Paris");
+        }
+        other => panic!("unexpected content {other:?}"),
+    }
+
+    let tokens = client(&server)
+        .build()
+        .unwrap()
+        .count_tokens()
+        .await
+        .unwrap();
+    assert!(tokens > 0);
+
+    let http = reqwest::Client::new();
+    let models: Value = http
+        .get(format!("{}/v1/models", server.url()))
+        .header("x-api-key", "test-key")
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(!models["data"].as_array().unwrap().is_empty());
+
+    server.reply(ScriptedReply::overloaded());
+    let batch: Value = http
+        .post(format!("{}/v1/messages/batches", server.url()))
+        .header("x-api-key", "test-key")
+        .json(&json!({"requests": [
+            {"custom_id": "a", "params": {"model": "claude-3-opus-20240229", "messages": []}},
+            {"custom_id": "b", "params": {"model": "claude-3-opus-20240229", "messages": []}},
+        ]}))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(batch["request_counts"]["errored"], 1);
+    assert_eq!(batch["request_counts"]["succeeded"], 1);
+
+    let results = http
+        .get(batch["results_url"].as_str().unwrap())
+        .header("x-api-key", "test-key")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    let results: Vec<Value> = results
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(results[0]["result"]["type"], "errored");
+    assert_eq!(results[1]["custom_id"], "b");
+
+    let unauthorized = http
+        .get(format!("{}/v1/models", server.url()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unauthorized.status(), 401);
+}
@@ -0,0 +1,58 @@
+use anthropic_sdk::{Client, Model, ValidationError, ValidationIssue};
+use serde_json::json;
+
+fn client(model: &str) -> Client {
+    Client::new()
+        .auth("test-key")
+        .model(model)
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+}
+
+#[test]
+fn looks_up_aliases_and_dated_snapshots() {
+    let by_alias = Model::lookup("claude-sonnet-4-5").unwrap();
+    assert_eq!(by_alias.id, "claude-sonnet-4-5-20250929");
+    assert_eq!(
+        Model::lookup("claude-sonnet-4-5-20991231").unwrap().id,
+        by_alias.id
+    );
+    assert!(Model::lookup("claude-sonnet-4-5-preview").is_none());
+}
+
+#[test]
+fn registered_models_are_used_for_validation() {
+    assert!(client("claude-test-1").max_tokens(500_000).build().is_ok());
+
+    Model::register(Model {
+        id: "claude-test-1-20990101".to_string(),
+        aliases: vec!["claude-test-1".to_string()],
+        max_output_tokens: 100_000,
+        ..Model::lookup("claude-sonnet-4-5").unwrap()
+    });
+
+    let error = client("claude-test-1")
+        .max_tokens(500_000)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.downcast_ref::<ValidationError>().unwrap().issues,
+        vec![ValidationIssue::MaxTokensAboveLimit {
+            model: "claude-test-1".to_string(),
+            max_tokens: 500_000,
+            limit: 100_000,
+        }]
+    );
+}
+
+#[test]
+fn clamps_max_tokens_to_the_smallest_limit() {
+    let request = client("claude-sonnet-4-5")
+        .fallback_models(["claude-opus-4-1"])
+        .max_tokens(100_000)
+        .clamp_max_tokens(true)
+        .build()
+        .unwrap();
+
+    assert_eq!(request.body()["max_tokens"], 32_000);
+}
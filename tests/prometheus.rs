@@ -0,0 +1,90 @@
+#![cfg(feature = "prometheus")]
+
+mod common;
+
+use anthropic_sdk::{Client, PrometheusMetrics};
+use common::{http_response, serve, MESSAGE};
+use serde_json::json;
+
+#[tokio::test]
+async fn renders_request_metrics() {
+    let (base_url, _) = serve(vec![
+        http_response("503 Service Unavailable", &[], "{}"),
+        http_response(
+            "200 OK",
+            &[
+                ("content-type", "application/json"),
+                ("anthropic-ratelimit-requests-remaining", "41"),
+            ],
+            MESSAGE,
+        ),
+    ])
+    .await;
+
+    let metrics = PrometheusMetrics::new();
+    Client::new()
+        .auth("test-key")
+        .base_url(&base_url)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .max_retries(1)
+        .prometheus(metrics.clone())
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+
+    let rendered = metrics.render();
+    for line in [
+        r#"anthropic_requests_total{error_type="none",model="claude-3-opus-20240229",status="200"} 1"#,
+        r#"anthropic_retries_total{model="claude-3-opus-20240229"} 1"#,
+        r#"anthropic_tokens_total{model="claude-3-opus-20240229",type="input"} 5"#,
+        r#"anthropic_rate_limit_remaining{resource="requests"} 41"#,
+        r#"anthropic_request_duration_seconds_count{model="claude-3-opus-20240229"} 1"#,
+    ] {
+        assert!(rendered.contains(line), "missing {line} in\n{rendered}");
+    }
+}
+
+#[tokio::test]
+async fn labels_api_errors_and_the_served_model() {
+    let overloaded =
+        r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+    let served = MESSAGE.replace("claude-3-opus-20240229", "claude-haiku-4-5-20251001");
+    let (base_url, _) = serve(vec![
+        http_response("529 Overloaded", &[], overloaded),
+        http_response("200 OK", &[("content-type", "application/json")], &served),
+        http_response("529 Overloaded", &[], overloaded),
+    ])
+    .await;
+
+    let metrics = PrometheusMetrics::new();
+    let client = Client::new()
+        .auth("test-key")
+        .base_url(&base_url)
+        .model("claude-sonnet-4-5")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .max_retries(0)
+        .prometheus(metrics.clone());
+    client
+        .clone()
+        .fallback_models(["claude-haiku-4-5"])
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+    let result = client.build().unwrap().execute_and_return_json().await;
+    assert!(result.is_err());
+
+    let rendered = metrics.render();
+    for line in [
+        r#"anthropic_requests_total{error_type="none",model="claude-haiku-4-5-20251001",status="200"} 1"#,
+        r#"anthropic_tokens_total{model="claude-haiku-4-5-20251001",type="input"} 5"#,
+        r#"anthropic_requests_total{error_type="overloaded_error",model="claude-sonnet-4-5",status="529"} 1"#,
+    ] {
+        assert!(rendered.contains(line), "missing {line} in\n{rendered}");
+    }
+    assert!(!rendered.contains(r#"error_type="_OTHER""#), "{rendered}");
+}
@@ -0,0 +1,110 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use anthropic_sdk::{ApiError, Client, RateLimiter, RateLimits};
+use common::{http_response, serve, MESSAGE};
+use serde_json::json;
+
+fn client(base_url: &str) -> Client {
+    Client::new()
+        .auth("test-key")
+        .base_url(base_url)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .max_tokens(16)
+}
+
+#[tokio::test]
+async fn retries_after_429() {
+    let (base_url, count) = serve(vec![
+        http_response("429 Too Many Requests", &[("retry-after", "0")], "{}"),
+        http_response(
+            "200 OK",
+            &[
+                ("content-type", "application/json"),
+                ("request-id", "req_1"),
+            ],
+            MESSAGE,
+        ),
+    ])
+    .await;
+
+    let response = client(&base_url)
+        .max_retries(1)
+        .rate_limiter(RateLimiter::new(RateLimits::default()))
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    assert_eq!(response.request_id(), Some("req_1"));
+}
+
+#[tokio::test]
+async fn waits_for_capacity_reported_by_headers() {
+    let exhausted = [
+        ("content-type", "application/json"),
+        ("anthropic-ratelimit-requests-limit", "60"),
+        ("anthropic-ratelimit-requests-remaining", "0"),
+    ];
+    let (base_url, count) = serve(vec![
+        http_response("200 OK", &exhausted, MESSAGE),
+        http_response("200 OK", &exhausted, MESSAGE),
+    ])
+    .await;
+
+    let rate_limiter = RateLimiter::new(RateLimits::default());
+    let client = client(&base_url).rate_limiter(rate_limiter);
+
+    client
+        .clone()
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+    let started = Instant::now();
+    client
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    assert!(started.elapsed() >= Duration::from_millis(900));
+}
+
+#[tokio::test]
+async fn errors_carry_request_id_and_rate_limits() {
+    let (base_url, _) = serve(vec![http_response(
+        "429 Too Many Requests",
+        &[
+            ("request-id", "req_1"),
+            ("retry-after", "30"),
+            ("anthropic-ratelimit-tokens-remaining", "0"),
+        ],
+        r#"{"type":"error","error":{"type":"rate_limit_error","message":"Rate limited"}}"#,
+    )])
+    .await;
+
+    let error = client(&base_url)
+        .max_retries(0)
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap_err();
+
+    let error = error.downcast_ref::<ApiError>().unwrap();
+    assert_eq!(error.status.as_u16(), 429);
+    assert_eq!(error.error_type.as_deref(), Some("rate_limit_error"));
+    assert_eq!(error.message, "Rate limited");
+    assert_eq!(error.request_id.as_deref(), Some("req_1"));
+    assert_eq!(error.rate_limit.tokens.remaining, Some(0));
+    assert_eq!(error.rate_limit.retry_after, Some(Duration::from_secs(30)));
+}
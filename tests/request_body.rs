@@ -0,0 +1,123 @@
+use anthropic_sdk::{Client, MockTransport};
+use serde_json::{json, Value};
+
+fn minimal_client() -> Client {
+    Client::new()
+        .auth("test-key")
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+}
+
+fn full_client() -> Client {
+    minimal_client()
+        .max_tokens(256)
+        .system("Be brief")
+        .temperature(0.5)
+        .top_k(10)
+        .top_p(0.9)
+        .stream(true)
+        .stop_sequences(vec!["END".to_string()])
+        .metadata(&json!({"user_id": "user-1"}))
+        .tools(&json!([{
+            "name": "get_weather",
+            "input_schema": {"type": "object", "properties": {}}
+        }]))
+        .tool_choice(json!({"type": "auto"}))
+}
+
+fn builder_body(client: Client) -> Value {
+    let request = client.builder().unwrap().build().unwrap();
+    serde_json::from_slice(request.body().unwrap().as_bytes().unwrap()).unwrap()
+}
+
+#[test]
+fn unset_fields_are_omitted() {
+    let request = minimal_client().build().unwrap();
+
+    assert_eq!(
+        request.body(),
+        &json!({
+            "model": "claude-3-opus-20240229",
+            "max_tokens": 1024,
+            "messages": [{"role": "user", "content": "Hello"}]
+        })
+    );
+}
+
+#[test]
+fn set_fields_are_sent() {
+    let request = full_client().build().unwrap();
+
+    assert_eq!(
+        request.body(),
+        &json!({
+            "model": "claude-3-opus-20240229",
+            "max_tokens": 256,
+            "messages": [{"role": "user", "content": "Hello"}],
+            "system": [{
+                "type": "text",
+                "text": "Be brief",
+                "cache_control": {"type": "ephemeral"}
+            }],
+            "temperature": 0.5,
+            "top_k": 10,
+            "top_p": 0.9,
+            "stream": true,
+            "stop_sequences": ["END"],
+            "metadata": {"user_id": "user-1"},
+            "tools": [{
+                "name": "get_weather",
+                "input_schema": {"type": "object", "properties": {}}
+            }],
+            "tool_choice": {"type": "auto"}
+        })
+    );
+}
+
+#[test]
+fn build_and_builder_send_the_same_body() {
+    let clamped = full_client().max_tokens(1_000_000).clamp_max_tokens(true);
+    for client in [minimal_client(), full_client(), clamped] {
+        let built = client.clone().build().unwrap().body().clone();
+        assert_eq!(built, builder_body(client));
+    }
+}
+
+#[test]
+fn to_request_body_shows_the_built_body() {
+    let client = full_client().max_tokens(1_000_000).clamp_max_tokens(true);
+    let exported: Value = serde_json::from_str(&client.to_request_body()).unwrap();
+
+    assert_eq!(&exported, client.build().unwrap().body());
+    assert_eq!(exported["max_tokens"], 4096);
+}
+
+#[test]
+fn to_curl_masks_the_key_and_quotes_the_body() {
+    let curl = minimal_client()
+        .messages(&json!([{"role": "user", "content": "It's me"}]))
+        .build()
+        .unwrap()
+        .to_curl();
+
+    assert!(curl.starts_with("curl 'https://api.anthropic.com/v1/messages'"));
+    assert!(curl.contains(r#"-H "x-api-key: $ANTHROPIC_API_KEY""#));
+    assert!(!curl.contains("test-key"));
+    assert!(curl.contains("-H 'anthropic-version: 2023-06-01'"));
+    assert!(curl.contains(r#""content":"It'\''s me""#));
+}
+
+#[test]
+fn prepare_returns_the_request_without_sending_it() {
+    let transport = MockTransport::new();
+    let request = minimal_client()
+        .transport(transport.clone())
+        .build()
+        .unwrap();
+
+    let prepared = request.prepare();
+    assert_eq!(&prepared.body, request.body());
+    assert_eq!(prepared.url, "https://api.anthropic.com/v1/messages");
+    assert_eq!(prepared.curl, request.to_curl());
+    assert!(transport.requests().is_empty());
+}
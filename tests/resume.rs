@@ -0,0 +1,93 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anthropic_sdk::{Client, StreamError};
+use common::{serve, serve_recording, sse_response, stop_events, text_events};
+use serde_json::json;
+
+const OVERLOADED: &str = "event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n";
+
+fn client(base_url: &str) -> Client {
+    Client::new()
+        .auth("test-key")
+        .base_url(base_url)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Tell me a story"}]))
+        .stream(true)
+}
+
+fn immediately(events: &[String]) -> Vec<(Duration, &str)> {
+    events
+        .iter()
+        .map(|event| (Duration::ZERO, event.as_str()))
+        .collect()
+}
+
+#[tokio::test]
+async fn resumes_stream_after_overloaded_error() {
+    let mut interrupted = text_events(&["Once ", "upon "]);
+    interrupted.push(OVERLOADED.to_string());
+    let mut continuation = text_events(&[" a time"]);
+    continuation.extend(stop_events(4));
+    let (base_url, count, requests) = serve_recording(vec![
+        sse_response(&immediately(&interrupted), false),
+        sse_response(&immediately(&continuation), false),
+    ])
+    .await;
+
+    let received = Arc::new(Mutex::new(String::new()));
+    let response = client(&base_url)
+        .max_tokens(100)
+        .resume_streams(1)
+        .build()
+        .unwrap()
+        .execute(|text| {
+            let received = received.clone();
+            async move { received.lock().unwrap().push_str(&text) }
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    assert_eq!(response.text, "Once upon a time");
+    assert_eq!(*received.lock().unwrap(), "Once upon a time");
+    assert_eq!(response.recovered, 1);
+    assert_eq!(response.usage.input_tokens, 10);
+    assert_eq!(response.usage.output_tokens, 5);
+    assert_eq!(response.stop_reason.as_deref(), Some("end_turn"));
+
+    let resumed = &requests.lock().unwrap()[1];
+    let body: serde_json::Value =
+        serde_json::from_str(&resumed[resumed.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+    assert_eq!(
+        body["messages"],
+        json!([
+            {"role": "user", "content": "Tell me a story"},
+            {"role": "assistant", "content": "Once upon"},
+        ])
+    );
+    assert_eq!(body["max_tokens"], 99);
+}
+
+#[tokio::test]
+async fn stream_errors_are_returned_without_resume() {
+    let mut interrupted = text_events(&["Once "]);
+    interrupted.push(OVERLOADED.to_string());
+    let (base_url, count) = serve(vec![sse_response(&immediately(&interrupted), false)]).await;
+
+    let error = client(&base_url)
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap_err();
+
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+    assert_eq!(
+        error.downcast_ref::<StreamError>().unwrap().error_type,
+        "overloaded_error"
+    );
+}
@@ -0,0 +1,39 @@
+use anthropic_sdk::{Client, MockResponse, MockTransport};
+use serde_json::json;
+
+const KEY: &str = "sk-ant-api03-secret-value";
+
+fn client() -> Client {
+    Client::new()
+        .auth(KEY)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+}
+
+#[test]
+fn debug_output_masks_the_key() {
+    let debug = format!("{:?}", client());
+
+    assert!(!debug.contains(KEY));
+    assert!(debug.contains("sk-ant-…****"));
+    assert!(!format!("{:?}", client().auth("other-key")).contains("other-key"));
+}
+
+#[tokio::test]
+async fn requests_and_exports_leave_out_the_key() {
+    let transport = MockTransport::new().reply(MockResponse::message("Hi"));
+    client()
+        .transport(transport.clone())
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+
+    let request = &transport.requests()[0];
+    assert_eq!(request.headers["x-api-key"], KEY);
+    assert!(!format!("{request:?}").contains(KEY));
+
+    let prepared = client().build().unwrap().prepare();
+    assert!(!format!("{prepared:?}").contains(KEY));
+}
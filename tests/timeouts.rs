@@ -0,0 +1,141 @@
+mod common;
+
+use std::time::Duration;
+
+use anthropic_sdk::{Client, Timeout, TimeoutPhase};
+use common::{http_response, serve, sse_response, stop_events, text_events, unreachable, MESSAGE};
+use serde_json::json;
+
+const PING: &str = "event: ping\ndata: {\"type\": \"ping\"}\n\n";
+
+fn client(base_url: &str) -> Client {
+    Client::new()
+        .auth("test-key")
+        .base_url(base_url)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .stream(true)
+        .max_retries(0)
+        .idle_timeout(Duration::from_millis(300))
+}
+
+#[tokio::test]
+async fn stalled_stream_times_out_with_partial_output() {
+    let events = text_events(&["Hello"]);
+    let script: Vec<_> = events
+        .iter()
+        .map(|e| (Duration::ZERO, e.as_str()))
+        .collect();
+    let (base_url, _) = serve(vec![sse_response(&script, true)]).await;
+
+    let error = client(&base_url)
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap_err();
+
+    let timeout = error.downcast_ref::<Timeout>().unwrap();
+    assert_eq!(timeout.phase, TimeoutPhase::Idle);
+    assert_eq!(timeout.partial.text, "Hello");
+}
+
+#[tokio::test]
+async fn pings_keep_a_slow_stream_alive() {
+    let mut events = text_events(&["Hello"]);
+    events.extend(std::iter::repeat_n(PING.to_string(), 4));
+    events.extend(stop_events(3));
+    let script: Vec<_> = events
+        .iter()
+        .map(|e| (Duration::from_millis(150), e.as_str()))
+        .collect();
+    let (base_url, _) = serve(vec![sse_response(&script, false)]).await;
+
+    let response = client(&base_url)
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap();
+
+    assert_eq!(response.text, "Hello");
+    assert_eq!(response.usage.output_tokens, 3);
+    assert_eq!(response.stop_reason.as_deref(), Some("end_turn"));
+}
+
+#[tokio::test]
+async fn unreachable_server_times_out_connecting() {
+    let server = unreachable();
+
+    let error = client(&server.base_url)
+        .connect_timeout(Duration::from_millis(200))
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap_err();
+
+    let timeout = error.downcast_ref::<Timeout>().unwrap();
+    assert_eq!(timeout.phase, TimeoutPhase::Connect);
+    assert_eq!(timeout.after, Duration::from_millis(200));
+    assert!(timeout.partial.text.is_empty());
+}
+
+#[tokio::test]
+async fn late_headers_time_out_waiting_for_the_first_byte() {
+    let reply = http_response("200 OK", &[("content-type", "application/json")], MESSAGE)
+        .delayed(Duration::from_secs(2));
+    let (base_url, _) = serve(vec![reply]).await;
+
+    let error = client(&base_url)
+        .first_byte_timeout(Duration::from_millis(200))
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap_err();
+
+    let timeout = error.downcast_ref::<Timeout>().unwrap();
+    assert_eq!(timeout.phase, TimeoutPhase::FirstByte);
+    assert!(timeout.partial.text.is_empty());
+}
+
+#[tokio::test]
+async fn slow_live_stream_hits_the_total_deadline() {
+    let events = text_events(&["One ", "two ", "three ", "four ", "five "]);
+    let script: Vec<_> = events
+        .iter()
+        .map(|e| (Duration::from_millis(100), e.as_str()))
+        .collect();
+    let (base_url, _) = serve(vec![sse_response(&script, true)]).await;
+
+    let error = client(&base_url)
+        .total_timeout(Duration::from_millis(350))
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap_err();
+
+    let timeout = error.downcast_ref::<Timeout>().unwrap();
+    assert_eq!(timeout.phase, TimeoutPhase::Total);
+    assert!(timeout.partial.text.starts_with("One "));
+    assert!(!timeout.partial.text.contains("five"));
+}
+
+#[tokio::test]
+async fn slow_body_that_keeps_arriving_is_not_idle() {
+    let reply = http_response("200 OK", &[("content-type", "application/json")], MESSAGE)
+        .trickle(4, Duration::from_millis(150));
+    let (base_url, _) = serve(vec![reply]).await;
+
+    let response = client(&base_url)
+        .stream(false)
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+
+    assert_eq!(response.usage.output_tokens, 1);
+}
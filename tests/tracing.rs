@@ -0,0 +1,141 @@
+#![cfg(feature = "tracing")]
+
+mod common;
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+use anthropic_sdk::Client;
+use common::{http_response, serve, MESSAGE};
+use serde_json::json;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// Keeps the last value recorded for every span field.
+#[derive(Clone, Default)]
+struct Fields(Arc<Mutex<HashMap<String, String>>>);
+
+impl Fields {
+    fn get(&self, name: &str) -> Option<String> {
+        self.0.lock().unwrap().get(name).cloned()
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(field.name().to_owned(), value.to_owned());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(field.name().to_owned(), format!("{value:?}"));
+    }
+}
+
+impl Subscriber for Fields {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        span.record(&mut self.clone());
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, values: &Record<'_>) {
+        values.record(&mut self.clone());
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, _: &Event<'_>) {}
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+fn client(base_url: &str) -> Client {
+    Client::new()
+        .auth("test-key")
+        .base_url(base_url)
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .max_tokens(16)
+        .max_retries(0)
+}
+
+#[tokio::test]
+async fn records_the_response_on_the_span() {
+    let (base_url, _) = serve(vec![http_response(
+        "200 OK",
+        &[
+            ("content-type", "application/json"),
+            ("request-id", "req_1"),
+        ],
+        MESSAGE,
+    )])
+    .await;
+    let fields = Fields::default();
+    let _guard = tracing::subscriber::set_default(fields.clone());
+
+    client(&base_url)
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        fields.get("gen_ai.request.model").as_deref(),
+        Some("claude-3-opus-20240229")
+    );
+    assert_eq!(fields.get("gen_ai.response.id").as_deref(), Some("msg_1"));
+    assert_eq!(
+        fields.get("gen_ai.usage.output_tokens").as_deref(),
+        Some("1")
+    );
+    assert_eq!(
+        fields.get("http.response.status_code").as_deref(),
+        Some("200")
+    );
+    assert_eq!(fields.get("anthropic.request_id").as_deref(), Some("req_1"));
+    assert_eq!(fields.get("error.type"), None);
+}
+
+#[tokio::test]
+async fn records_the_api_error_type() {
+    let (base_url, _) = serve(vec![http_response(
+        "529 Overloaded",
+        &[("request-id", "req_2")],
+        r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+    )])
+    .await;
+    let fields = Fields::default();
+    let _guard = tracing::subscriber::set_default(fields.clone());
+
+    let result = client(&base_url)
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await;
+
+    assert!(result.is_err());
+    assert_eq!(fields.get("otel.status_code").as_deref(), Some("ERROR"));
+    assert_eq!(
+        fields.get("error.type").as_deref(),
+        Some("overloaded_error")
+    );
+    assert_eq!(
+        fields.get("http.response.status_code").as_deref(),
+        Some("529")
+    );
+    assert_eq!(fields.get("anthropic.request_id").as_deref(), Some("req_2"));
+}
@@ -0,0 +1,67 @@
+use std::sync::{Arc, Mutex};
+
+use anthropic_sdk::{Client, MockResponse, MockTransport};
+use serde_json::json;
+
+fn client(transport: &MockTransport) -> Client {
+    Client::new()
+        .auth("test-key")
+        .model("claude-3-opus-20240229")
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .transport(transport.clone())
+}
+
+#[tokio::test]
+async fn streams_scripted_events() {
+    let transport = MockTransport::new().reply(MockResponse::text_stream(&["Hello", " there"]));
+
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let response = client(&transport)
+        .stream(true)
+        .build()
+        .unwrap()
+        .execute(|text| {
+            let received = received.clone();
+            async move { received.lock().unwrap().push(text) }
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(*received.lock().unwrap(), vec!["Hello", " there"]);
+    assert_eq!(response.text, "Hello there");
+    assert_eq!(response.usage.output_tokens, 2);
+}
+
+#[tokio::test]
+async fn retries_scripted_errors_and_records_requests() {
+    let transport = MockTransport::new()
+        .reply(MockResponse::error(529, "overloaded_error", "Overloaded").header("retry-after", "0"))
+        .reply(MockResponse::message("Hi").header("request-id", "req_mock"));
+
+    let response = client(&transport)
+        .max_retries(1)
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+
+    assert_eq!(response.request_id(), Some("req_mock"));
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].url.path(), "/v1/messages");
+    assert_eq!(requests[1].headers["x-api-key"], "test-key");
+    assert_eq!(requests[1].body["model"], "claude-3-opus-20240229");
+}
+
+#[tokio::test]
+async fn fails_when_the_script_runs_out() {
+    let error = client(&MockTransport::new())
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap_err();
+
+    assert!(error.to_string().contains("no response left"));
+}
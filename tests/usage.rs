@@ -0,0 +1,173 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anthropic_sdk::{Budget, BudgetExceeded, CancellationToken, Client, UsageTracker};
+use common::{http_response, serve, sse_response, stop_events, text_events};
+use serde_json::json;
+
+const BATCH_MESSAGE: &str = r#"{"id":"msg_1","model":"claude-sonnet-4-5-20250929","stop_reason":"end_turn","role":"assistant","content":[{"type":"text","text":"Hi"}],"usage":{"input_tokens":1000,"output_tokens":200,"cache_read_input_tokens":10000,"cache_creation_input_tokens":0,"server_tool_use":{"web_search_requests":2},"service_tier":"batch"}}"#;
+
+fn client(base_url: &str, model: &str, tracker: &UsageTracker) -> Client {
+    Client::new()
+        .auth("test-key")
+        .base_url(base_url)
+        .model(model)
+        .messages(&json!([{"role": "user", "content": "Hello"}]))
+        .max_tokens(1024)
+        .usage_tracker(tracker.clone())
+}
+
+#[tokio::test]
+async fn tracks_usage_and_cost_by_model_and_tag() {
+    let mut events = text_events(&["Hi"]);
+    events.extend(stop_events(4));
+    let (base_url, _) = serve(vec![
+        http_response(
+            "200 OK",
+            &[("content-type", "application/json")],
+            BATCH_MESSAGE,
+        ),
+        sse_response(
+            &events
+                .iter()
+                .map(|event| (Duration::ZERO, event.as_str()))
+                .collect::<Vec<_>>(),
+            false,
+        ),
+    ])
+    .await;
+
+    let tracker = UsageTracker::new();
+    client(&base_url, "claude-sonnet-4-5", &tracker)
+        .usage_tag("search")
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+    client(&base_url, "claude-3-opus-20240229", &tracker)
+        .stream(true)
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .unwrap();
+
+    let by_tag = tracker.by_tag();
+    let search = by_tag[&Some("search".to_string())];
+    assert_eq!(search.requests, 1);
+    assert_eq!(search.cache_read_input_tokens, 10_000);
+    assert_eq!(search.web_search_requests, 2);
+    assert_eq!(search.batch_requests, 1);
+    assert!((search.cost_usd - 0.0245).abs() < 1e-9);
+
+    let untagged = by_tag[&None];
+    assert_eq!(untagged.input_tokens, 5);
+    assert_eq!(untagged.output_tokens, 4);
+    assert!((untagged.cost_usd - 0.000375).abs() < 1e-9);
+
+    assert_eq!(tracker.total().requests, 2);
+    let csv = tracker.to_csv();
+    assert_eq!(csv.lines().count(), 3);
+    assert!(
+        csv.contains("\nclaude-sonnet-4-5-20250929,search,1,1000,200,0,10000,2,1,0,0,0.024500\n")
+    );
+}
+
+#[tokio::test]
+async fn budgets_refuse_requests_once_spent() {
+    let expensive = r#"{"id":"msg_1","model":"claude-3-opus-20240229","stop_reason":"end_turn","role":"assistant","content":[{"type":"text","text":"Hi"}],"usage":{"input_tokens":1000,"output_tokens":100}}"#;
+    let (base_url, count) = serve(vec![http_response(
+        "200 OK",
+        &[("content-type", "application/json")],
+        expensive,
+    )])
+    .await;
+
+    let alerts = Arc::new(AtomicUsize::new(0));
+    let tracker = UsageTracker::new()
+        .budget(Budget::hard(0.01).tag("agent"))
+        .budget({
+            let alerts = alerts.clone();
+            Budget::soft(0.005, move |_| {
+                alerts.fetch_add(1, Ordering::SeqCst);
+            })
+        });
+    let agent = client(&base_url, "claude-3-opus-20240229", &tracker)
+        .max_tokens(100)
+        .usage_tag("agent");
+
+    agent
+        .clone()
+        .build()
+        .unwrap()
+        .execute_and_return_json()
+        .await
+        .unwrap();
+    assert_eq!(alerts.load(Ordering::SeqCst), 1);
+
+    let error = agent.build().err().unwrap();
+    let exceeded = error.downcast_ref::<BudgetExceeded>().unwrap();
+    assert_eq!(exceeded.tag.as_deref(), Some("agent"));
+    assert!((exceeded.spent_usd - 0.0225).abs() < 1e-9);
+    assert_eq!(count.load(Ordering::SeqCst), 1);
+
+    assert!(client(&base_url, "claude-3-opus-20240229", &tracker)
+        .build()
+        .is_ok());
+}
+
+#[tokio::test]
+async fn records_the_partial_usage_of_failed_and_cancelled_streams() {
+    let mut failed = text_events(&["Once"]);
+    failed.push("event: error\ndata: {\"type\":\"error\",\"error\":{\"type\":\"invalid_request_error\",\"message\":\"Bad\"}}\n\n".to_string());
+    let cancelled = text_events(&["Once"]);
+    let (base_url, _) = serve(vec![
+        sse_response(
+            &failed
+                .iter()
+                .map(|event| (Duration::ZERO, event.as_str()))
+                .collect::<Vec<_>>(),
+            false,
+        ),
+        sse_response(
+            &cancelled
+                .iter()
+                .map(|event| (Duration::ZERO, event.as_str()))
+                .collect::<Vec<_>>(),
+            true,
+        ),
+    ])
+    .await;
+
+    let tracker = UsageTracker::new();
+    let request = client(&base_url, "claude-3-opus-latest", &tracker).stream(true);
+    assert!(request
+        .clone()
+        .build()
+        .unwrap()
+        .execute(|_| async {})
+        .await
+        .is_err());
+    let token = CancellationToken::new();
+    assert!(request
+        .build()
+        .unwrap()
+        .cancellation_token(token.clone())
+        .execute(|_| {
+            let token = token.clone();
+            async move { token.cancel() }
+        })
+        .await
+        .is_err());
+
+    let by_model = tracker.by_model();
+    assert_eq!(by_model.len(), 1);
+    let opus = by_model["claude-3-opus-20240229"];
+    assert_eq!(opus.requests, 2);
+    assert_eq!(opus.input_tokens, 10);
+    assert_eq!(opus.output_tokens, 2);
+}